@@ -171,6 +171,12 @@ async function pdaOrderbook(pool: PublicKey, progId: PublicKey) {
     progId
   );
 }
+async function pdaOpenOrders(pool: PublicKey, owner: PublicKey, progId: PublicKey) {
+  return await PublicKey.findProgramAddress(
+    [Buffer.from("v3"), Buffer.from("open_orders"), pool.toBuffer(), owner.toBuffer()],
+    progId
+  );
+}
 function receiptNonceBuf(nonce: number) {
   return new BN(nonce).toArrayLike(Buffer, "le", 8);
 }
@@ -181,6 +187,13 @@ async function pdaPosition(pool: PublicKey, owner: PublicKey, receiptNonce: numb
     progId
   );
 }
+async function pdaRangePosition(pool: PublicKey, owner: PublicKey, receiptNonce: number, progId: PublicKey) {
+  const nb = receiptNonceBuf(receiptNonce);
+  return await PublicKey.findProgramAddress(
+    [Buffer.from("v3"), Buffer.from("range"), pool.toBuffer(), owner.toBuffer(), nb],
+    progId
+  );
+}
 
 /* ---------------------------- ATA helper ------------------------------- */
 
@@ -363,7 +376,9 @@ export async function addLiquidity(
   amountA: BN,
   amountB: BN,
   receiptNonce: number,
-  minUnlockAfterSlots: BN = new BN(0)
+  minUnlockAfterSlots: BN = new BN(0),
+  minSharesOut: BN = new BN(1),
+  expirySlot: BN | null = null
 ) {
   const env = await resolveRuntime();
   const progClient = env.program;
@@ -381,12 +396,15 @@ export async function addLiquidity(
 
   const [posPda] = await pdaPosition(poolPda, payerPub, receiptNonce, progId);
 
+  // default expiry: ~150 slots from now
+  const expiry = expirySlot ?? new BN((await env.connection.getSlot("confirmed")) + 150);
+
   // Try both method name variants
   const builder = (progClient as any).methods?.addLiquidity ?? (progClient as any).methods?.add_liquidity;
   if (!builder) throw new Error("Program client missing addLiquidity builder (check IDL).");
 
   try {
-    const rpc = await builder(bandIdx, amountA, amountB, new BN(receiptNonce), minUnlockAfterSlots)
+    const rpc = await builder(bandIdx, amountA, amountB, new BN(receiptNonce), minUnlockAfterSlots, minSharesOut, expiry)
       .accounts({
         user: payerPub,
         pool: poolPda,
@@ -409,15 +427,115 @@ export async function addLiquidity(
   }
 }
 
-/** placeOrder helper (simplified) */
+/** addRangeLiquidity helper: one position over bands [lowerBand, upperBand].
+ *  modeObj is { Uniform: {} }, { Weight: {} } or { Custom: {} } (with one weight per band). */
+export async function addRangeLiquidity(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  lowerBand: number,
+  upperBand: number,
+  amountA: BN,
+  amountB: BN,
+  modeObj: any,
+  receiptNonce: number,
+  weightsBps: number[] = [],
+  minSharesOut: BN = new BN(1),
+  expirySlot: BN | null = null
+) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const [vaultA] = await pdaVault(poolPda, mintA, progId);
+  const [vaultB] = await pdaVault(poolPda, mintB, progId);
+
+  const payer = env.payerKeypair;
+  const payerPub = payer.publicKey;
+  const userAtaA = await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider);
+  const userAtaB = await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider);
+  const [rangePda] = await pdaRangePosition(poolPda, payerPub, receiptNonce, progId);
+  const expiry = expirySlot ?? new BN((await env.connection.getSlot("confirmed")) + 150);
+
+  const builder = (progClient as any).methods?.addRangeLiquidity ?? (progClient as any).methods?.add_range_liquidity;
+  if (!builder) throw new Error("Program client missing addRangeLiquidity builder (check IDL).");
+
+  try {
+    const rpc = await builder({
+      lowerBand,
+      upperBand,
+      amountA,
+      amountB,
+      mode: modeObj,
+      weightsBps,
+      receiptNonce: new BN(receiptNonce),
+      minUnlockAfterSlots: new BN(0),
+      minSharesOut,
+      expirySlot: expiry,
+    })
+      .accounts({
+        user: payerPub,
+        pool: poolPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        user_ata_a: userAtaA,
+        user_ata_b: userAtaB,
+        position: rangePda,
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+        mint_a: mintA,
+        mint_b: mintB,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(rpc, "confirmed");
+    return rpc;
+  } catch (err) {
+    console.error("addRangeLiquidity failed:", err);
+    throw err;
+  }
+}
+
+/** collectRangeFees helper: collects fees from every band of a range position. */
+export async function collectRangeFees(mintA: PublicKey, mintB: PublicKey, receiptNonce: number) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payer = env.payerKeypair;
+  const payerPub = payer.publicKey;
+
+  const builder = (progClient as any).methods?.collectRangeFees ?? (progClient as any).methods?.collect_range_fees;
+  if (!builder) throw new Error("Program client missing collectRangeFees builder (check IDL).");
+
+  const rpc = await builder()
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      position: (await pdaRangePosition(poolPda, payerPub, receiptNonce, progId))[0],
+      vault_a: (await pdaVault(poolPda, mintA, progId))[0],
+      vault_b: (await pdaVault(poolPda, mintB, progId))[0],
+      treasury_a: (await pdaTreasury(poolPda, mintA, progId))[0],
+      treasury_b: (await pdaTreasury(poolPda, mintB, progId))[0],
+      user_ata_a: await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider),
+      user_ata_b: await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider),
+      token_program: TOKEN_PROGRAM_ID,
+      mint_a: mintA,
+      mint_b: mintB,
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** placeOrder helper: book fills are credited to the payer's OpenOrders (see settleFunds),
+ *  a resting remainder is escrowed in the pool vaults (B for bids, A for asks). */
 export async function placeOrder(
   mintA: PublicKey,
   mintB: PublicKey,
   sideObj: any, // { Bid: {} } or { Ask: {} }
   qty: BN,
   limitPriceOpt: BN | null,
-  tifParam: any,
-  postOnly = false,
+  tifParam: any, // { kind: 0 IOC | 1 GTC | 2 GTT | 3 FOK, gtt_expiry_slot }
+  postOnly: any = { None: {} }, // { None: {} } | { Reject: {} } | { Slide: {} }
   reduceOnly = false,
   clientId: BN = new BN(0)
 ) {
@@ -427,6 +545,7 @@ export async function placeOrder(
   const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
   const [poolPda] = await pdaPool(mintA, mintB, progId);
   const [orderbookPda] = await pdaOrderbook(poolPda, progId);
+  const payer = env.payerKeypair;
 
   const builder = (progClient as any).methods?.placeOrder ?? (progClient as any).methods?.place_order;
   if (!builder) throw new Error("Program client missing placeOrder builder (check IDL).");
@@ -434,9 +553,20 @@ export async function placeOrder(
   try {
     const rpc = await builder(sideObj, qty, limitPriceOpt, tifParam, postOnly, reduceOnly, clientId)
       .accounts({
-        user: env.payerKeypair.publicKey,
+        user: payerPub,
         pool: poolPda,
         orderbook: orderbookPda,
+        vault_a: (await pdaVault(poolPda, mintA, progId))[0],
+        vault_b: (await pdaVault(poolPda, mintB, progId))[0],
+        treasury_a: (await pdaTreasury(poolPda, mintA, progId))[0],
+        treasury_b: (await pdaTreasury(poolPda, mintB, progId))[0],
+        user_ata_a: await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider),
+        user_ata_b: await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider),
+        open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+        mint_a: mintA,
+        mint_b: mintB,
       })
       .rpc();
     await env.connection.confirmTransaction(rpc, "confirmed");
@@ -447,8 +577,353 @@ export async function placeOrder(
   }
 }
 
+/** placeOrdersBatch helper: places up to 20 limit orders in one transaction. Each entry is
+ *  { side, qty, price_1e6, tif, client_id }; `cancelExisting` pulls the payer's quotes first. */
+export async function placeOrdersBatch(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  orders: any[],
+  postOnly: any = { None: {} },
+  cancelExisting = false
+) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const payerPub = env.payerKeypair.publicKey;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payer = env.payerKeypair;
+
+  const builder = (progClient as any).methods?.placeOrdersBatch ?? (progClient as any).methods?.place_orders_batch;
+  if (!builder) throw new Error("Program client missing placeOrdersBatch builder (check IDL).");
+
+  const rpc = await builder(orders, postOnly, cancelExisting)
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+      vault_a: (await pdaVault(poolPda, mintA, progId))[0],
+      vault_b: (await pdaVault(poolPda, mintB, progId))[0],
+      treasury_a: (await pdaTreasury(poolPda, mintA, progId))[0],
+      treasury_b: (await pdaTreasury(poolPda, mintB, progId))[0],
+      user_ata_a: await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider),
+      user_ata_b: await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider),
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+      token_program: TOKEN_PROGRAM_ID,
+      system_program: SystemProgram.programId,
+      mint_a: mintA,
+      mint_b: mintB,
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** amendOrder helper: shrinks one of the payer's resting orders in place (same price) or
+ *  cancel-replaces it at `newPriceOpt`. `orderRef` is { Id: [orderId] } or { ClientId: [clientId] }. */
+export async function amendOrder(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  orderRef: any,
+  newQty: BN,
+  newPriceOpt: BN | null = null,
+  postOnly: any = { None: {} }
+) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const payerPub = env.payerKeypair.publicKey;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payer = env.payerKeypair;
+
+  const builder = (progClient as any).methods?.amendOrder ?? (progClient as any).methods?.amend_order;
+  if (!builder) throw new Error("Program client missing amendOrder builder (check IDL).");
+
+  const rpc = await builder(orderRef, newQty, newPriceOpt, postOnly)
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+      vault_a: (await pdaVault(poolPda, mintA, progId))[0],
+      vault_b: (await pdaVault(poolPda, mintB, progId))[0],
+      treasury_a: (await pdaTreasury(poolPda, mintA, progId))[0],
+      treasury_b: (await pdaTreasury(poolPda, mintB, progId))[0],
+      user_ata_a: await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider),
+      user_ata_b: await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider),
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+      token_program: TOKEN_PROGRAM_ID,
+      system_program: SystemProgram.programId,
+      mint_a: mintA,
+      mint_b: mintB,
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** cancelOrder helper: cancels one of the payer's resting orders; its escrow becomes
+ *  settleable via settleFunds. */
+export async function cancelOrder(mintA: PublicKey, mintB: PublicKey, sideObj: any, orderId: BN) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const [orderbookPda] = await pdaOrderbook(poolPda, progId);
+  const payer = env.payerKeypair;
+  const payerPub = payer.publicKey;
+
+  const builder = (progClient as any).methods?.cancelOrder ?? (progClient as any).methods?.cancel_order;
+  if (!builder) throw new Error("Program client missing cancelOrder builder (check IDL).");
+
+  const rpc = await builder(sideObj, orderId)
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: orderbookPda,
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** cancelByClientId helper: cancels every resting order of the payer tagged `clientId`. */
+export async function cancelByClientId(mintA: PublicKey, mintB: PublicKey, clientId: BN) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payerPub = env.payerKeypair.publicKey;
+
+  const builder = (progClient as any).methods?.cancelByClientId ?? (progClient as any).methods?.cancel_by_client_id;
+  if (!builder) throw new Error("Program client missing cancelByClientId builder (check IDL).");
+
+  const rpc = await builder(clientId)
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** cancelAllOrders helper: cancels all of the payer's resting orders, or only one side
+ *  ({ Bid: {} } / { Ask: {} }); the escrow becomes settleable via settleFunds. */
+export async function cancelAllOrders(mintA: PublicKey, mintB: PublicKey, sideObj: any = null) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payerPub = env.payerKeypair.publicKey;
+
+  const builder = (progClient as any).methods?.cancelAllOrders ?? (progClient as any).methods?.cancel_all_orders;
+  if (!builder) throw new Error("Program client missing cancelAllOrders builder (check IDL).");
+
+  const rpc = await builder(sideObj)
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** consumeEvents helper: applies up to `limit` queued fills/outs to the given owners'
+ *  OpenOrders (read the queue to find them). Returns the transaction signature. */
+export async function consumeEvents(mintA: PublicKey, mintB: PublicKey, owners: PublicKey[], limit = 16) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+
+  const builder = (progClient as any).methods?.consumeEvents ?? (progClient as any).methods?.consume_events;
+  if (!builder) throw new Error("Program client missing consumeEvents builder (check IDL).");
+
+  const rpc = await builder(limit)
+    .accounts({
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+    })
+    .remainingAccounts(
+      await Promise.all(
+        owners.map(async (owner) => ({
+          pubkey: (await pdaOpenOrders(poolPda, owner, progId))[0],
+          isWritable: true,
+          isSigner: false,
+        }))
+      )
+    )
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** settleFunds helper: pays the payer's free OpenOrders balances to their ATAs. */
+export async function settleFunds(mintA: PublicKey, mintB: PublicKey) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const payer = env.payerKeypair;
+  const payerPub = payer.publicKey;
+
+  const builder = (progClient as any).methods?.settleFunds ?? (progClient as any).methods?.settle_funds;
+  if (!builder) throw new Error("Program client missing settleFunds builder (check IDL).");
+
+  const rpc = await builder()
+    .accounts({
+      user: payerPub,
+      pool: poolPda,
+      orderbook: (await pdaOrderbook(poolPda, progId))[0],
+      open_orders: (await pdaOpenOrders(poolPda, payerPub, progId))[0],
+      vault_a: (await pdaVault(poolPda, mintA, progId))[0],
+      vault_b: (await pdaVault(poolPda, mintB, progId))[0],
+      user_ata_a: await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider),
+      user_ata_b: await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider),
+      token_program: TOKEN_PROGRAM_ID,
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
+/** Shared account plumbing for the swap entrypoints. */
+async function callSwap(
+  methodCandidates: string[],
+  mintA: PublicKey,
+  mintB: PublicKey,
+  sideObj: any,
+  amount: BN,
+  bound: BN,
+  expirySlot: BN | null
+) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const [vaultA] = await pdaVault(poolPda, mintA, progId);
+  const [vaultB] = await pdaVault(poolPda, mintB, progId);
+  const [treasuryA] = await pdaTreasury(poolPda, mintA, progId);
+  const [treasuryB] = await pdaTreasury(poolPda, mintB, progId);
+
+  const payer = env.payerKeypair;
+  const payerPub = payer.publicKey;
+  const userAtaA = await getOrCreateATA(env.connection, payer, mintA, payerPub, env.provider);
+  const userAtaB = await getOrCreateATA(env.connection, payer, mintB, payerPub, env.provider);
+
+  // default expiry: ~150 slots from now
+  const expiry = expirySlot ?? new BN((await env.connection.getSlot("confirmed")) + 150);
+
+  const name = methodCandidates.find((m) => (progClient as any).methods?.[m]);
+  if (!name) throw new Error(`Program client missing ${methodCandidates[0]} builder (check IDL).`);
+
+  try {
+    const rpc = await (progClient as any).methods[name](sideObj, amount, bound, expiry)
+      .accounts({
+        user: payerPub,
+        pool: poolPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        user_ata_a: userAtaA,
+        user_ata_b: userAtaB,
+        token_program: TOKEN_PROGRAM_ID,
+        mint_a: mintA,
+        mint_b: mintB,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(rpc, "confirmed");
+    return rpc;
+  } catch (err) {
+    console.error(`${name} failed:`, err);
+    throw err;
+  }
+}
+
+/** swap helper: exact-in against band liquidity. Bid pays B for A, Ask pays A for B. */
+export async function swap(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  sideObj: any, // { Bid: {} } or { Ask: {} }
+  amountIn: BN,
+  minAmountOut: BN,
+  expirySlot: BN | null = null
+) {
+  return await callSwap(["swap"], mintA, mintB, sideObj, amountIn, minAmountOut, expirySlot);
+}
+
+/** swapExactOut helper: receive exactly `amountOut`, paying at most `maxAmountIn`. */
+export async function swapExactOut(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  sideObj: any, // { Bid: {} } or { Ask: {} }
+  amountOut: BN,
+  maxAmountIn: BN,
+  expirySlot: BN | null = null
+) {
+  return await callSwap(["swapExactOut", "swap_exact_out"], mintA, mintB, sideObj, amountOut, maxAmountIn, expirySlot);
+}
+
+/** quoteSwap helper: simulates an IOC taker order of `qty` base via the `quote_swap` view
+ *  (no transaction sent), routed through the book and bands like `place_order`. */
+export async function quoteSwap(
+  mintA: PublicKey,
+  mintB: PublicKey,
+  sideObj: any, // { Bid: {} } or { Ask: {} }
+  qty: BN,
+  limitPrice1e6: BN | null = null,
+  expirySlot: BN | null = null
+) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const [orderbookPda] = await pdaOrderbook(poolPda, progId);
+
+  const builder = (progClient as any).methods?.quoteSwap ?? (progClient as any).methods?.quote_swap;
+  if (!builder) throw new Error("Program client missing quoteSwap builder (check IDL).");
+
+  // default expiry: ~150 slots from now
+  const expiry = expirySlot ?? new BN((await env.connection.getSlot("confirmed")) + 150);
+  return await builder(sideObj, qty, limitPrice1e6, expiry)
+    .accounts({ pool: poolPda, orderbook: orderbookPda })
+    .view();
+}
+
 /* Utility functions */
 
+/** rebalanceBands helper: keeper crank moving pool-owned liquidity toward band weights. */
+export async function rebalanceBands(mintA: PublicKey, mintB: PublicKey) {
+  const env = await resolveRuntime();
+  const progClient = env.program;
+  const progId: PublicKey = (progClient as any).programId ?? PROGRAM_ID;
+  const [poolPda] = await pdaPool(mintA, mintB, progId);
+  const [vaultA] = await pdaVault(poolPda, mintA, progId);
+  const [vaultB] = await pdaVault(poolPda, mintB, progId);
+
+  const builder = (progClient as any).methods?.rebalanceBands ?? (progClient as any).methods?.rebalance_bands;
+  if (!builder) throw new Error("Program client missing rebalanceBands builder (check IDL).");
+
+  const rpc = await builder()
+    .accounts({
+      caller: env.payerKeypair.publicKey,
+      pool: poolPda,
+      vault_a: vaultA,
+      vault_b: vaultB,
+      mint_a: mintA,
+      mint_b: mintB,
+    })
+    .rpc();
+  await env.connection.confirmTransaction(rpc, "confirmed");
+  return rpc;
+}
+
 export async function viewPoolState(mintA: PublicKey, mintB: PublicKey) {
   const env = await resolveRuntime();
   const progClient = env.program;
@@ -481,7 +956,20 @@ export async function showMyAddressAndBalance() {
         initializePool,
         postYieldsAndUpdate,
         addLiquidity,
+        addRangeLiquidity,
+        collectRangeFees,
         placeOrder,
+        placeOrdersBatch,
+        amendOrder,
+        cancelOrder,
+        cancelByClientId,
+        cancelAllOrders,
+        consumeEvents,
+        settleFunds,
+        swap,
+        swapExactOut,
+        quoteSwap,
+        rebalanceBands,
         viewPoolState,
         showMyAddressAndBalance,
       };
@@ -496,7 +984,20 @@ export default {
   initializePool,
   postYieldsAndUpdate,
   addLiquidity,
+  addRangeLiquidity,
+  collectRangeFees,
   placeOrder,
+  placeOrdersBatch,
+  amendOrder,
+  cancelOrder,
+  cancelByClientId,
+  cancelAllOrders,
+  consumeEvents,
+  settleFunds,
+  swap,
+  swapExactOut,
+  quoteSwap,
+  rebalanceBands,
   viewPoolState,
   showMyAddressAndBalance,
 };
@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 use solana_program::keccak;
 
@@ -19,7 +20,7 @@ pub mod yield_sensitive_dlmm {
         let pool_key = ctx.accounts.pool.key();
 
         let pool = &mut ctx.accounts.pool;
-        pool.version = 3;
+        pool.version = POOL_VERSION;
         pool.bump = ctx.bumps.pool;
 
         // roles
@@ -86,6 +87,12 @@ pub mod yield_sensitive_dlmm {
         pool.fee_k_per_bps = p.fee_k_per_bps;
         pool.fee_max_bps = p.fee_max_bps;
         pool.fee_current_bps = p.fee_base_bps;
+        pool.protocol_fee_bps = 0;
+        pool.protocol_fees_a = 0;
+        pool.protocol_fees_b = 0;
+        pool.next_band_id = 0;
+        pool.bounty_budget_a = 0;
+        pool.bounty_budget_b = 0;
 
         // fee schedule knobs (CLOB split)
         pool.maker_rebate_max_bps = p.maker_rebate_max_bps;
@@ -143,44 +150,129 @@ pub mod yield_sensitive_dlmm {
         Ok(())
     }
 
-/// Per-pool migration: bring an existing Pool account up to the v3 layout/semantics.
-/// Idempotent and admin-gated. Captures the pool key before taking a mutable borrow
-/// to avoid borrow checker errors when emitting events that reference the key.
-pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
-    // capture immutable values BEFORE the mutable borrow
-    let pool_key = ctx.accounts.pool.key();
-
-    // Now take the mutable borrow for updates
-    let pool = &mut ctx.accounts.pool;
-
-    require!(pool.version < 3, DlmmError::AlreadyMigrated);
+/// Per-pool migration: rewrite a pre-v4 Pool account in the current layout (band ids,
+/// pool-owned/locked share counters, protocol fee fields). The account is decoded by hand
+/// because `Account<Pool>` cannot read the old layout, so owner, seeds and admin are checked
+/// against the legacy bytes. Admin-gated; fails with `AlreadyMigrated` once at v4.
+/// A pre-v4 orderbook, if the pool has one, is rebuilt empty in the slab layout; those books
+/// never held escrow, so there is nothing to refund.
+pub fn migrate_pool_versions(ctx: Context<MigratePool>) -> Result<()> {
+    let info = ctx.accounts.pool.to_account_info();
+    let pool_key = info.key();
+
+    let legacy = {
+        let data = info.try_borrow_data()?;
+        require!(data.len() > 8 && data[..8] == Pool::DISCRIMINATOR, ErrorCode::AccountDiscriminatorMismatch);
+        require!(data[8] < POOL_VERSION, DlmmError::AlreadyMigrated);
+        LegacyPool::deserialize(&mut &data[8..]).map_err(|_| error!(ErrorCode::AccountDidNotDeserialize))?
+    };
+    let expected = Pubkey::create_program_address(
+        &[b"v3".as_ref(), b"pool".as_ref(), legacy.mint_a.as_ref(), legacy.mint_b.as_ref(), &[legacy.bump]],
+        ctx.program_id,
+    )
+    .map_err(|_| error!(ErrorCode::ConstraintSeeds))?;
+    require_keys_eq!(expected, pool_key, ErrorCode::ConstraintSeeds);
+    require!(is_admin(&legacy.admins, &ctx.accounts.any_admin.key()), DlmmError::Unauthorized);
 
-    let from_version = pool.version;
-    pool.version = 3;
+    let from_version = legacy.version;
+    let mut current = legacy.into_current();
 
-    if pool.alpha_twap_bps == 0 {
-        pool.alpha_twap_bps = 500; // 5%
+    if current.alpha_twap_bps == 0 {
+        current.alpha_twap_bps = 500; // 5%
     }
-    if pool.max_twap_dev_bps == 0 {
-        pool.max_twap_dev_bps = 500; // 5%
+    if current.max_twap_dev_bps == 0 {
+        current.max_twap_dev_bps = 500; // 5%
     }
 
     // If you need derived state refreshed
-    recompute_bands(pool, /*enforce_cb=*/false, /*weights_only=*/false)?;
+    recompute_bands(&mut current, /*enforce_cb=*/false, /*weights_only=*/false)?;
+
+    {
+        let mut data = info.try_borrow_mut_data()?;
+        data.fill(0);
+        current.try_serialize(&mut &mut data[..])?;
+    }
+
+    let ob_info = ctx.accounts.orderbook.to_account_info();
+    if !ob_info.data_is_empty() {
+        require_keys_eq!(*ob_info.owner, crate::ID, ErrorCode::ConstraintOwner);
+        let old = {
+            let data = ob_info.try_borrow_data()?;
+            require!(data[..8] == OrderBook::DISCRIMINATOR, ErrorCode::AccountDiscriminatorMismatch);
+            LegacyOrderBook::deserialize(&mut &data[8..]).map_err(|_| error!(ErrorCode::AccountDidNotDeserialize))?
+        };
+        let ob = OrderBook {
+            bump: old.bump,
+            pool: old.pool,
+            tick_1e6: old.tick_1e6,
+            next_order_id: old.next_order_id,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            event_q_head: 0,
+            event_q: vec![BookEvent::default(); EVENT_Q_CAP],
+            max_levels: old.max_levels.min(MAX_LEVELS as u16),
+            max_queue_per_level: old.max_queue_per_level,
+            orders: Vec::new(),
+            free_head: ORDER_NIL,
+            escrow_a: 0,
+            escrow_b: 0,
+            event_q_tail: 0,
+            min_order_qty: DEFAULT_MIN_ORDER_QTY,
+        };
+        let mut data = ob_info.try_borrow_mut_data()?;
+        data.fill(0);
+        ob.try_serialize(&mut &mut data[..])?;
+    }
 
-    // safe to use pool_key (captured earlier) in event
     let now = Clock::get()?.slot;
     emit!(PoolMigratedV {
         event_version: EVENT_VERSION,
         pool: pool_key,
         from: from_version,
-        to: pool.version,
+        to: current.version,
         migrated_at_slot: now,
     });
 
     Ok(())
 }
 
+/// Rewrites a pre-v4 Position (`band_idx: u8`) as a v4 one (`band_id: u32`). The pool must be
+/// migrated first: that gives its bands ids in index order, so the old index is the band's id.
+/// Permissionless; `payer` tops up rent for the 3 extra bytes.
+pub fn migrate_position(ctx: Context<MigratePosition>) -> Result<()> {
+    require!(ctx.accounts.pool.version >= POOL_VERSION, DlmmError::PoolNotMigrated);
+    let info = ctx.accounts.position.to_account_info();
+
+    let legacy = {
+        let data = info.try_borrow_data()?;
+        require!(data.len() > 8 && data[..8] == Position::DISCRIMINATOR, ErrorCode::AccountDiscriminatorMismatch);
+        require!(data.len() < 8 + Position::SIZE, DlmmError::AlreadyMigrated);
+        LegacyPosition::deserialize(&mut &data[8..]).map_err(|_| error!(ErrorCode::AccountDidNotDeserialize))?
+    };
+    require_keys_eq!(legacy.pool, ctx.accounts.pool.key(), ErrorCode::ConstraintHasOne);
+
+    let new_len = 8 + Position::SIZE;
+    let rent = Rent::get()?.minimum_balance(new_len);
+    if info.lamports() < rent {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: info.clone(),
+                },
+            ),
+            rent - info.lamports(),
+        )?;
+    }
+    info.realloc(new_len, true)?;
+
+    let pos = legacy.into_current();
+    let mut data = info.try_borrow_mut_data()?;
+    pos.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
     /* ----------------------- Governance (timelock/quorum) ------------------- */
 
     pub fn propose_params(
@@ -281,6 +373,192 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         Ok(())
     }
 
+    /// Withdraws accrued protocol fees from the treasuries. Bounded by `protocol_fees_*`,
+    /// so LP-owed fees and any bounty budget held in the treasuries stay untouched.
+    pub fn withdraw_protocol_fees(ctx: Context<FeeScoped>, amount_a: u64, amount_b: u64) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool_ai = ctx.accounts.pool.to_account_info();
+
+        let pool = &mut ctx.accounts.pool;
+        require!(amount_a > 0 || amount_b > 0, DlmmError::ZeroAmount);
+        require!(
+            amount_a <= pool.protocol_fees_a && amount_b <= pool.protocol_fees_b,
+            DlmmError::InvalidAmount
+        );
+        pool.protocol_fees_a -= amount_a;
+        pool.protocol_fees_b -= amount_b;
+
+        let seeds = pool_signer_seeds(&*pool);
+        let signer = &[&seeds[..]];
+        if amount_a > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.treasury_a.to_account_info(),
+                        to: ctx.accounts.dst_a.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.treasury_b.to_account_info(),
+                        to: ctx.accounts.dst_b.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                amount_b,
+            )?;
+        }
+
+        emit!(ProtocolFeesWithdrawnV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            amount_a,
+            amount_b,
+        });
+        Ok(())
+    }
+
+    /// Deploys accrued protocol fees as pool-owned liquidity, spread over active bands by
+    /// `weight_bps`. Amounts a band can't take (an empty band below the locked minimum)
+    /// stay in the treasury as protocol fees.
+    pub fn seed_pool_liquidity(ctx: Context<PolScoped>, amount_a: u64, amount_b: u64) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool_ai = ctx.accounts.pool.to_account_info();
+
+        let pool = &mut ctx.accounts.pool;
+        require!(!pool.is_paused && !pool.pause_deposits, DlmmError::Paused);
+        require!(amount_a > 0 || amount_b > 0, DlmmError::ZeroAmount);
+        require!(
+            amount_a <= pool.protocol_fees_a && amount_b <= pool.protocol_fees_b,
+            DlmmError::InvalidAmount
+        );
+
+        let n = pool.n_bands as usize;
+        let w_sum: u128 = pool.bands[..n]
+            .iter()
+            .filter(|b| b.is_active)
+            .map(|b| u128::from(b.weight_bps))
+            .sum();
+        require!(w_sum > 0, DlmmError::InvalidDistribution);
+
+        let (mut in_a, mut in_b, mut minted) = (0u64, 0u64, 0u64);
+        for b in pool.bands[..n].iter_mut().filter(|b| b.is_active) {
+            let add_a = (u128::from(amount_a) * u128::from(b.weight_bps) / w_sum) as u64;
+            let add_b = (u128::from(amount_b) * u128::from(b.weight_bps) / w_sum) as u64;
+            let shares = mint_pol(b, add_a, add_b)?;
+            if shares == 0 { continue; }
+            in_a += add_a;
+            in_b += add_b;
+            minted = minted.saturating_add(shares);
+        }
+        require!(minted > 0, DlmmError::ZeroShares);
+        pool.protocol_fees_a -= in_a;
+        pool.protocol_fees_b -= in_b;
+
+        let seeds = pool_signer_seeds(&*pool);
+        let signer = &[&seeds[..]];
+        if in_a > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.treasury_a.to_account_info(),
+                        to: ctx.accounts.vault_a.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                in_a,
+            )?;
+        }
+        if in_b > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.treasury_b.to_account_info(),
+                        to: ctx.accounts.vault_b.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                in_b,
+            )?;
+        }
+
+        emit!(PoolLiquiditySeededV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            amount_a: in_a,
+            amount_b: in_b,
+            shares: minted,
+        });
+
+        assert_invariants(
+            &ctx.accounts.mint_a,
+            &ctx.accounts.mint_b,
+            &ctx.accounts.vault_a,
+            &ctx.accounts.vault_b,
+            &*pool,
+        )?;
+        Ok(())
+    }
+
+    /// Tops up the keeper bounty budget: moves tokens from the funder into the treasuries and
+    /// credits `bounty_budget_*`, the only balance `pay_bounty_if_any` pays from. Anyone may fund.
+    pub fn fund_bounty_budget(ctx: Context<FundBounty>, amount_a: u64, amount_b: u64) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        require!(amount_a > 0 || amount_b > 0, DlmmError::ZeroAmount);
+
+        if amount_a > 0 {
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.funder_ata_a.to_account_info(),
+                        to: ctx.accounts.treasury_a.to_account_info(),
+                        authority: ctx.accounts.funder.to_account_info(),
+                    },
+                ),
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.funder_ata_b.to_account_info(),
+                        to: ctx.accounts.treasury_b.to_account_info(),
+                        authority: ctx.accounts.funder.to_account_info(),
+                    },
+                ),
+                amount_b,
+            )?;
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        pool.bounty_budget_a = pool.bounty_budget_a.checked_add(amount_a).ok_or(DlmmError::MathOverflow)?;
+        pool.bounty_budget_b = pool.bounty_budget_b.checked_add(amount_b).ok_or(DlmmError::MathOverflow)?;
+        emit!(BountyBudgetFundedV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            amount_a,
+            amount_b,
+        });
+        Ok(())
+    }
+
     pub fn set_updater(ctx: Context<OpsScoped>, updater: Pubkey, oracle: Option<Pubkey>) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
         pool.updater = updater;
@@ -510,8 +788,104 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         Ok(())
     }
 
+    /// Keeper crank: moves pool-owned liquidity from bands above their `weight_bps` target
+    /// to bands below it. At most `max_weight_shift_bps` of the pool-owned value (in B at
+    /// each band's price) moves per call. LP shares are untouched: pool-owned shares are
+    /// burned and minted at the same per-share value as `remove_liquidity`/`add_liquidity`.
+    pub fn rebalance_bands(ctx: Context<RebalanceBands>) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+
+        require!(!pool.is_paused && !pool.pause_bands, DlmmError::Paused);
+        require!(
+            ctx.accounts.caller.key() == pool.updater || is_admin(&pool.admins, &ctx.accounts.caller.key()),
+            DlmmError::Unauthorized
+        );
+
+        let n = pool.n_bands as usize;
+        let mut held = Vec::with_capacity(n);
+        for b in pool.bands[..n].iter() {
+            held.push(pol_value(b)?);
+        }
+        let total: u128 = held.iter().sum();
+        require!(total > 0, DlmmError::InsufficientLiquidity);
+
+        let w_sum: u128 = pool.bands[..n]
+            .iter()
+            .filter(|b| b.is_active)
+            .map(|b| u128::from(b.weight_bps))
+            .sum();
+        require!(w_sum > 0, DlmmError::InvalidDistribution);
+        let target: Vec<u128> = pool.bands[..n]
+            .iter()
+            .map(|b| if b.is_active { total * u128::from(b.weight_bps) / w_sum } else { 0 })
+            .collect();
+
+        // pull from over-weight bands until the per-call budget is spent
+        let mut budget = total * u128::from(pool.max_weight_shift_bps) / 10_000u128;
+        let (mut pot_a, mut pot_b) = (0u64, 0u64);
+        let mut moved: u128 = 0;
+        let mut src: Option<usize> = None;
+        for i in 0..n {
+            if budget == 0 { break; }
+            let take = held[i].saturating_sub(target[i]).min(budget);
+            if take == 0 { continue; }
+            let (out_a, out_b) = burn_pol(&mut pool.bands[i], take, held[i]);
+            pot_a += out_a;
+            pot_b += out_b;
+            budget -= take;
+            moved += take;
+            src.get_or_insert(i);
+        }
+
+        // push into under-weight bands in proportion to their deficit
+        let deficit: Vec<u128> = (0..n).map(|i| target[i].saturating_sub(held[i])).collect();
+        let d_sum: u128 = deficit.iter().sum();
+        let last = deficit.iter().rposition(|d| *d > 0);
+        let (mut left_a, mut left_b) = (pot_a, pot_b);
+        for (i, d) in deficit.iter().enumerate() {
+            if *d == 0 { continue; }
+            // the last receiver also takes the rounding dust
+            let (add_a, add_b) = if Some(i) == last {
+                (left_a, left_b)
+            } else {
+                ((u128::from(pot_a) * d / d_sum) as u64, (u128::from(pot_b) * d / d_sum) as u64)
+            };
+            if mint_pol(&mut pool.bands[i], add_a, add_b)? > 0 {
+                left_a -= add_a;
+                left_b -= add_b;
+            }
+        }
+        // anything no band could take goes back where it came from; reserves that mint no
+        // shares would sit in the vaults unowned, so revert instead
+        if left_a > 0 || left_b > 0 {
+            let i = src.ok_or(DlmmError::InvariantViolated)?;
+            require!(mint_pol(&mut pool.bands[i], left_a, left_b)? > 0, DlmmError::InsufficientLiquidity);
+        }
+
+        assert_invariants(
+            &ctx.accounts.mint_a,
+            &ctx.accounts.mint_b,
+            &ctx.accounts.vault_a,
+            &ctx.accounts.vault_b,
+            &*pool,
+        )?;
+
+        emit!(BandsRebalancedV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            moved_value_b: moved.min(u64::MAX as u128) as u64,
+            pol_value_b: total.min(u64::MAX as u128) as u64,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
     /* --------------------------- Liquidity ops ------------------------------ */
 
+    /// Deposits exactly `amount_a` / `amount_b` into one band. The amounts are the caller's own,
+    /// so the band state they get priced against is what can go stale: `min_shares_out` floors
+    /// what is minted, failing with `LiquidityBoundExceeded` instead of underpaying in shares.
     #[allow(clippy::too_many_arguments)]
     pub fn add_liquidity(
         ctx: Context<AddLiquidity>,
@@ -520,6 +894,8 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         amount_b: u64,
         receipt_nonce: u64,
         min_unlock_after_slots: u64,
+        min_shares_out: u64,
+        expiry_slot: u64,
     ) -> Result<()> {
         // capture immutable info before creating mutable borrow
         let pool_key = ctx.accounts.pool.key();
@@ -530,6 +906,7 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         require!(!pool.is_paused && !pool.pause_deposits, DlmmError::Paused);
         require!((band_idx as usize) < pool.n_bands as usize, DlmmError::InvalidBandIndex);
         require!(amount_a > 0 || amount_b > 0, DlmmError::ZeroAmount);
+        require!(Clock::get()?.slot <= expiry_slot, DlmmError::Expired);
 
         // ratio guard
         require!(
@@ -542,6 +919,9 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
             .get_mut(band_idx as usize)
             .ok_or(DlmmError::InvalidBandIndex)?;
         require!(b.is_active, DlmmError::BandInactive);
+        // a receipt reused via init_if_needed must stay on the band it was opened on
+        let pos = &ctx.accounts.position;
+        require!(pos.shares == 0 || pos.band_id == b.id, DlmmError::InvalidBandIndex);
 
         if amount_a > 0 {
             token::transfer(
@@ -570,9 +950,14 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
             )?;
         }
 
-        let shares = quote_shares_to_mint(b, amount_a, amount_b);
+        let (shares, locked) = quote_shares_to_mint(b, amount_a, amount_b)?;
         require!(shares > 0, DlmmError::ZeroShares);
-        b.total_shares = b.total_shares.saturating_add(shares);
+        require!(shares >= min_shares_out, DlmmError::LiquidityBoundExceeded);
+        b.total_shares = b
+            .total_shares
+            .checked_add(shares + locked)
+            .ok_or(DlmmError::MathOverflow)?;
+        b.locked_shares += locked;
         b.reserves_a = b.reserves_a.saturating_add(amount_a);
         b.reserves_b = b.reserves_b.saturating_add(amount_b);
         b.util_a = b.util_a.saturating_add(amount_a);
@@ -582,7 +967,7 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         pos.bump = ctx.bumps.position;
         pos.pool = pool_key;
         pos.owner = ctx.accounts.user.key();
-        pos.band_idx = band_idx;
+        pos.band_id = b.id;
         pos.shares = pos.shares.saturating_add(shares);
         pos.last_fee_growth_a_1e18 = b.fee_growth_a_1e18;
         pos.last_fee_growth_b_1e18 = b.fee_growth_b_1e18;
@@ -610,7 +995,14 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         Ok(())
     }
 
-    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, shares_to_burn: u64, close_position: bool) -> Result<()> {
+    pub fn remove_liquidity(
+        ctx: Context<RemoveLiquidity>,
+        shares_to_burn: u64,
+        close_position: bool,
+        min_out_a: u64,
+        min_out_b: u64,
+        expiry_slot: u64,
+    ) -> Result<()> {
         // capture immutable info first
         let pool_key = ctx.accounts.pool.key();
         let pool_ai = ctx.accounts.pool.to_account_info();
@@ -621,6 +1013,7 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
 
         let pos = &mut ctx.accounts.position;
         let now = Clock::get()?.slot;
+        require!(now <= expiry_slot, DlmmError::Expired);
         require!(now >= pos.min_unlock_slot, DlmmError::PositionLocked);
         require!(
             pos.owner == ctx.accounts.user.key() || pos.approved == Some(ctx.accounts.user.key()),
@@ -628,16 +1021,15 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         );
         require!(shares_to_burn > 0 && shares_to_burn <= pos.shares, DlmmError::InvalidAmount);
 
-        let b = pool
-            .bands
-            .get_mut(pos.band_idx as usize)
-            .ok_or(DlmmError::InvalidBandIndex)?;
+        let band_idx = band_index(pool, pos.band_id)?;
+        let b = &mut pool.bands[band_idx];
         require!(b.total_shares > 0, DlmmError::ZeroShares);
 
         let out_a =
             (u128::from(b.reserves_a) * u128::from(shares_to_burn) / u128::from(b.total_shares)) as u64;
         let out_b =
             (u128::from(b.reserves_b) * u128::from(shares_to_burn) / u128::from(b.total_shares)) as u64;
+        require!(out_a >= min_out_a && out_b >= min_out_b, DlmmError::LiquidityBoundExceeded);
 
         b.total_shares = b.total_shares.saturating_sub(shares_to_burn);
         b.reserves_a = b.reserves_a.saturating_sub(out_a);
@@ -683,7 +1075,7 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
             event_version: EVENT_VERSION,
             pool: pool_key,
             owner: pos.owner,
-            band_idx: pos.band_idx,
+            band_idx: band_idx as u8,
             shares_burned: shares_to_burn,
             out_a,
             out_b,
@@ -708,10 +1100,8 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
 
         let pos = &mut ctx.accounts.position;
-        let b = pool
-            .bands
-            .get_mut(pos.band_idx as usize)
-            .ok_or(DlmmError::InvalidBandIndex)?;
+        let band_idx = band_index(pool, pos.band_id)?;
+        let b = &pool.bands[band_idx];
         require!(pos.shares > 0 && b.total_shares > 0, DlmmError::ZeroShares);
 
         let owed_a = mul_div_1e18(
@@ -762,7 +1152,7 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
             event_version: EVENT_VERSION,
             pool: pool_key,
             owner: pos.owner,
-            band_idx: pos.band_idx,
+            band_idx: band_idx as u8,
             out_a: owed_a,
             out_b: owed_b
         });
@@ -787,235 +1177,719 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         Ok(())
     }
 
-    pub fn recenter_compact(ctx: Context<RiskScoped>) -> Result<()> {
+    /* ------------------------- Range positions ------------------------------ */
+
+    /// Deposits across `[lower_band, upper_band]` in one position. Both amounts are split
+    /// over the range by `mode` and each band mints shares as in `add_liquidity`.
+    pub fn add_range_liquidity(ctx: Context<AddRangeLiquidity>, p: RangeDepositParams) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pos_key = ctx.accounts.position.key();
+
         let pool = &mut ctx.accounts.pool;
-        mark_inactive_by_floor(pool);
-        compact_active_bands(pool);
-        renormalize_active_weights(pool)?;
-        Ok(())
-    }
 
-    /* --------------------------- Orderbook: init ---------------------------- */
+        require!(!pool.is_paused && !pool.pause_deposits, DlmmError::Paused);
+        require!(p.amount_a > 0 || p.amount_b > 0, DlmmError::ZeroAmount);
+        require!(Clock::get()?.slot <= p.expiry_slot, DlmmError::Expired);
+        require!(
+            p.lower_band <= p.upper_band && (p.upper_band as usize) < pool.n_bands as usize,
+            DlmmError::InvalidBandIndex
+        );
+        require!(
+            passes_ratio_guard(pool, p.amount_a, p.amount_b)?,
+            DlmmError::DepositRatioOutOfBounds
+        );
 
-    pub fn init_orderbook(ctx: Context<InitOrderBook>, tick_1e6: u64, max_levels: u16) -> Result<()> {
-        let pool = &ctx.accounts.pool;
-        require!(!pool.pause_orderbook, DlmmError::Paused);
-        require!(tick_1e6 > 0, DlmmError::ParamOutOfRange);
+        let weights = range_weights(pool, p.lower_band, p.upper_band, p.mode, &p.weights_bps)?;
+        let w_sum: u128 = weights.iter().map(|w| u128::from(*w)).sum();
+        require!(w_sum > 0, DlmmError::InvalidDistribution);
+
+        let mut legs = Vec::with_capacity(weights.len());
+        let (mut in_a, mut in_b, mut minted) = (0u64, 0u64, 0u64);
+        for (k, w) in weights.iter().enumerate() {
+            let b = &mut pool.bands[p.lower_band as usize + k];
+            let add_a = (u128::from(p.amount_a) * u128::from(*w) / w_sum) as u64;
+            let add_b = (u128::from(p.amount_b) * u128::from(*w) / w_sum) as u64;
+            let mut leg = RangeLeg {
+                band_id: b.id,
+                shares: 0,
+                last_fee_growth_a_1e18: b.fee_growth_a_1e18,
+                last_fee_growth_b_1e18: b.fee_growth_b_1e18,
+            };
+            if add_a > 0 || add_b > 0 {
+                require!(b.is_active, DlmmError::BandInactive);
+                let (shares, locked) = quote_shares_to_mint(b, add_a, add_b)?;
+                require!(shares > 0, DlmmError::ZeroShares);
+                b.total_shares = b
+                    .total_shares
+                    .checked_add(shares + locked)
+                    .ok_or(DlmmError::MathOverflow)?;
+                b.locked_shares += locked;
+                b.reserves_a = b.reserves_a.saturating_add(add_a);
+                b.reserves_b = b.reserves_b.saturating_add(add_b);
+                b.util_a = b.util_a.saturating_add(add_a);
+                b.util_b = b.util_b.saturating_add(add_b);
+                leg.shares = shares;
+                in_a += add_a;
+                in_b += add_b;
+                minted = minted.saturating_add(shares);
+            }
+            legs.push(leg);
+        }
+        require!(minted >= p.min_shares_out, DlmmError::LiquidityBoundExceeded);
 
-        let ob = &mut ctx.accounts.orderbook;
-        ob.bump = ctx.bumps.orderbook;
-        ob.pool = ctx.accounts.pool.key();
-        ob.tick_1e6 = tick_1e6;
-        ob.best_bid_band = -1;
-        ob.best_ask_band = -1;
-        ob.next_order_id = 1;
-        ob.event_q_head = 0;
-        ob.max_levels = max_levels.max(pool.n_bands as u16);
-        ob.max_queue_per_level = DEFAULT_MAX_QUEUE_PER_LEVEL;
+        if in_a > 0 {
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.user_ata_a.to_account_info(),
+                        to: ctx.accounts.vault_a.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
+                    },
+                ),
+                in_a,
+            )?;
+        }
+        if in_b > 0 {
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.user_ata_b.to_account_info(),
+                        to: ctx.accounts.vault_b.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
+                    },
+                ),
+                in_b,
+            )?;
+        }
 
-        let n = pool.n_bands as usize;
-        ob.bids.clear();
-        ob.asks.clear();
-        for i in 0..n {
-            ob.bids.push(PriceLevel { band_idx: i as i16, total_qty: 0, head: 0, tail: 0 });
-            ob.asks.push(PriceLevel { band_idx: i as i16, total_qty: 0, head: 0, tail: 0 });
+        let pos = &mut ctx.accounts.position;
+        pos.bump = ctx.bumps.position;
+        pos.pool = pool_key;
+        pos.owner = ctx.accounts.user.key();
+        pos.receipt_nonce = p.receipt_nonce;
+        pos.min_unlock_slot = Clock::get()?.slot.saturating_add(p.min_unlock_after_slots);
+        pos.legs = legs;
+
+        for (k, leg) in pos.legs.iter().enumerate() {
+            if leg.shares == 0 { continue; }
+            emit!(LiquidityAddedV {
+                event_version: EVENT_VERSION,
+                pool: pool_key,
+                owner: pos.owner,
+                band_idx: p.lower_band + k as u8,
+                shares: leg.shares,
+                receipt: pos_key,
+            });
         }
-        ob.event_q.clear();
-        ob.event_q.resize(EVENT_Q_CAP, BookEvent::default());
 
+        assert_invariants(
+            &ctx.accounts.mint_a,
+            &ctx.accounts.mint_b,
+            &ctx.accounts.vault_a,
+            &ctx.accounts.vault_b,
+            &*pool,
+        )?;
         Ok(())
     }
 
-    /* ----------------------- Orderbook: placement/cancel -------------------- */
+    /// Burns `share_bps` of every leg of a range position and pays out the proportional
+    /// reserves, together with the fees owed on every leg up to now (settled before the burn
+    /// so none are lost with the shares). The account is closed once all legs are empty and
+    /// `close_position` is set.
+    pub fn remove_range_liquidity(
+        ctx: Context<WithRangePosition>,
+        share_bps: u16,
+        close_position: bool,
+        min_out_a: u64,
+        min_out_b: u64,
+        expiry_slot: u64,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool_ai = ctx.accounts.pool.to_account_info();
+        let pos_key = ctx.accounts.position.key();
 
-    #[allow(clippy::too_many_arguments)]
+        let pool = &mut ctx.accounts.pool;
+
+        require!(!pool.is_paused && !pool.pause_withdraws, DlmmError::Paused);
+        require!(share_bps > 0 && share_bps <= 10_000, DlmmError::InvalidAmount);
+
+        let pos = &mut ctx.accounts.position;
+        let now = Clock::get()?.slot;
+        require!(now <= expiry_slot, DlmmError::Expired);
+        require!(now >= pos.min_unlock_slot, DlmmError::PositionLocked);
+
+        let (fee_a, fee_b) = accrue_range_fees(pool, pool_key, pos)?;
+
+        let owner = pos.owner;
+        let (mut out_a, mut out_b) = (0u64, 0u64);
+        for leg in pos.legs.iter_mut() {
+            let burn = (u128::from(leg.shares) * u128::from(share_bps) / 10_000u128) as u64;
+            if burn == 0 { continue; }
+            let i = band_index(pool, leg.band_id)?;
+            let band_idx = i as u8;
+            let b = &mut pool.bands[i];
+            require!(b.total_shares >= burn, DlmmError::ZeroShares);
+
+            let leg_a = (u128::from(b.reserves_a) * u128::from(burn) / u128::from(b.total_shares)) as u64;
+            let leg_b = (u128::from(b.reserves_b) * u128::from(burn) / u128::from(b.total_shares)) as u64;
+            b.total_shares -= burn;
+            b.reserves_a = b.reserves_a.saturating_sub(leg_a);
+            b.reserves_b = b.reserves_b.saturating_sub(leg_b);
+            leg.shares -= burn;
+            out_a += leg_a;
+            out_b += leg_b;
+
+            emit!(LiquidityRemovedV {
+                event_version: EVENT_VERSION,
+                pool: pool_key,
+                owner,
+                band_idx,
+                shares_burned: burn,
+                out_a: leg_a,
+                out_b: leg_b,
+                receipt: pos_key,
+            });
+        }
+        require!(out_a >= min_out_a && out_b >= min_out_b, DlmmError::LiquidityBoundExceeded);
+
+        let seeds = pool_signer_seeds(&*pool);
+        let signer = &[&seeds[..]];
+        if out_a > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_a.to_account_info(),
+                        to: ctx.accounts.user_ata_a.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                out_a,
+            )?;
+        }
+        if out_b > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_b.to_account_info(),
+                        to: ctx.accounts.user_ata_b.to_account_info(),
+                        authority: pool_ai.clone(),
+                    },
+                    signer,
+                ),
+                out_b,
+            )?;
+        }
+
+        assert_invariants(
+            &ctx.accounts.mint_a,
+            &ctx.accounts.mint_b,
+            &ctx.accounts.vault_a,
+            &ctx.accounts.vault_b,
+            &*pool,
+        )?;
+        pay_range_fees(ctx.accounts, fee_a, fee_b)?;
+
+        if close_position && ctx.accounts.position.legs.iter().all(|l| l.shares == 0) {
+            ctx.accounts.position.close(ctx.accounts.user.to_account_info())?;
+        }
+        Ok(())
+    }
+
+    /// Collects fees owed on every leg of a range position in one transfer per token.
+    pub fn collect_range_fees(ctx: Context<WithRangePosition>) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let (owed_a, owed_b) = accrue_range_fees(&ctx.accounts.pool, pool_key, &mut ctx.accounts.position)?;
+        pay_range_fees(ctx.accounts, owed_a, owed_b)
+    }
+
+    pub fn recenter_compact(ctx: Context<RiskScoped>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        mark_inactive_by_floor(pool);
+        compact_active_bands(pool);
+        renormalize_active_weights(pool)?;
+        Ok(())
+    }
+
+    /* -------------------------------- Swaps --------------------------------- */
+
+    /// Exact-in swap against band liquidity. `Side::Bid` pays token B for token A,
+    /// `Side::Ask` pays token A for token B. Only the input actually consumed by the
+    /// band walk is pulled from the user; `min_amount_out` bounds slippage. Swaps never
+    /// touch the book, whatever the `route_mode`: routing applies to `place_order`.
+    pub fn swap(
+        ctx: Context<Swap>,
+        side: Side,
+        amount_in: u64,
+        min_amount_out: u64,
+        expiry_slot: u64,
+    ) -> Result<u64> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+
+        require!(amount_in > 0, DlmmError::ZeroAmount);
+        check_swap_window(pool, expiry_slot)?;
+
+        let take = take_from_bands(pool, side, amount_in, swap_limit(side), SwapMode::ExactIn)?;
+        require!(take.amount_out > 0, DlmmError::ZeroAmount);
+        require!(take.amount_out >= min_amount_out, DlmmError::SlippageExceeded);
+        emit_band_fills(pool_key, &ctx.accounts.user.key(), side, &take);
+
+        settle_swap(ctx.accounts, side, &take)?;
+        Ok(take.amount_out)
+    }
+
+    /// Exact-out swap against band liquidity: pays exactly `amount_out` of the output
+    /// token or fails, pulling no more than `max_amount_in` of the input token.
+    pub fn swap_exact_out(
+        ctx: Context<Swap>,
+        side: Side,
+        amount_out: u64,
+        max_amount_in: u64,
+        expiry_slot: u64,
+    ) -> Result<u64> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+
+        require!(amount_out > 0, DlmmError::ZeroAmount);
+        check_swap_window(pool, expiry_slot)?;
+
+        let take = take_from_bands(pool, side, amount_out, swap_limit(side), SwapMode::ExactOut)?;
+        require!(take.amount_out == amount_out, DlmmError::InsufficientLiquidity);
+        require!(take.amount_in <= max_amount_in, DlmmError::SlippageExceeded);
+        emit_band_fills(pool_key, &ctx.accounts.user.key(), side, &take);
+
+        settle_swap(ctx.accounts, side, &take)?;
+        Ok(take.amount_in)
+    }
+
+    /// Read-only quote of a taker order of `qty` base: routes it through the book and the
+    /// bands in the pool's `route_mode`, as an IOC `place_order` with the same limit does,
+    /// on scratch copies of the pool and book, and returns the result as return data. Fails
+    /// where `place_order` or `swap` would: paused, in the post-only window, past
+    /// `expiry_slot` or below the book's minimum size. The taker is quoted with no resting
+    /// orders, so self-trade prevention never applies.
+    pub fn quote_swap(
+        ctx: Context<ViewPool>,
+        side: Side,
+        qty: u64,
+        limit_price_opt_1e6: Option<u64>,
+        expiry_slot: u64,
+    ) -> Result<SwapQuote> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &ctx.accounts.pool;
+        check_swap_window(pool, expiry_slot)?;
+        require!(!pool.pause_orderbook, DlmmError::Paused);
+        require!(qty > 0, DlmmError::ZeroAmount);
+
+        // decoded straight into the scratch copy: a clone of a full book would not fit the heap
+        let mut ob = OrderBook::try_deserialize(&mut &ctx.accounts.orderbook.try_borrow_data()?[..])?;
+        require!(qty >= ob.min_order_qty, DlmmError::OrderTooSmall);
+        let mut sim: Pool = (**pool).clone();
+        let price_1e6 = order_price(&sim, &ob, limit_price_opt_1e6)?;
+        let mut oo = OpenOrders {
+            bump: 0,
+            pool: pool_key,
+            owner: Pubkey::default(),
+            base_free: 0,
+            base_locked: 0,
+            quote_free: 0,
+            quote_locked: 0,
+        };
+        let mut flows = OrderFlows { quote_only: true, ..OrderFlows::default() };
+        let t = route_taker(&mut ob, &mut sim, pool_key, &Pubkey::default(), side, price_1e6, qty, &mut oo, &mut flows)?;
+
+        // book proceeds are credited to the taker's OpenOrders, band proceeds paid out
+        let (k_in, k_out, book_out) = match side {
+            Side::Bid => (1, 0, oo.base_free),
+            Side::Ask => (0, 1, oo.quote_free),
+        };
+        let mut q = SwapQuote {
+            amount_in: flows.user_to_vault[k_in] + flows.user_to_treasury[k_in],
+            amount_out: flows.vault_to_user[k_out] + book_out,
+            fee_paid: t.band_fee,
+            book_fee_b: t.book_fee,
+            book_qty: t.book_base,
+            bands_touched: sim
+                .bands
+                .iter()
+                .zip(pool.bands.iter())
+                .filter(|(s, b)| s.reserves_a != b.reserves_a || s.reserves_b != b.reserves_b)
+                .count() as u16,
+            effective_price_1e6: 0,
+        };
+        // effective price of token A in B, fees included
+        let (base, quote) = match side {
+            Side::Bid => (q.amount_out, q.amount_in),
+            Side::Ask => (q.amount_in, q.amount_out),
+        };
+        if base > 0 {
+            q.effective_price_1e6 = (u128::from(quote) * 1_000_000u128 / u128::from(base)) as u64;
+        }
+        Ok(q)
+    }
+
+    /* --------------------------- Orderbook: init ---------------------------- */
+
+    pub fn init_orderbook(ctx: Context<InitOrderBook>, tick_1e6: u64, max_levels: u16, min_order_qty: u64) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(!pool.pause_orderbook, DlmmError::Paused);
+        require!(tick_1e6 > 0 && min_order_qty > 0, DlmmError::ParamOutOfRange);
+        require!(max_levels > 0 && max_levels as usize <= MAX_LEVELS, DlmmError::ParamOutOfRange);
+
+        let ob = &mut ctx.accounts.orderbook;
+        ob.bump = ctx.bumps.orderbook;
+        ob.pool = ctx.accounts.pool.key();
+        ob.tick_1e6 = tick_1e6;
+        ob.next_order_id = 1;
+        ob.event_q_head = 0;
+        ob.event_q_tail = 0;
+        ob.max_levels = max_levels;
+        ob.max_queue_per_level = DEFAULT_MAX_QUEUE_PER_LEVEL;
+        ob.min_order_qty = min_order_qty;
+        ob.orders.clear();
+        ob.free_head = ORDER_NIL;
+        ob.escrow_a = 0;
+        ob.escrow_b = 0;
+
+        ob.bids.clear();
+        ob.asks.clear();
+        ob.event_q.clear();
+        ob.event_q.resize(EVENT_Q_CAP, BookEvent::default());
+
+        Ok(())
+    }
+
+    /* ----------------------- Orderbook: placement/cancel -------------------- */
+
+    /// Places an order of `qty` base (token A) at its tick-rounded limit price, which it keeps
+    /// for as long as it rests: band recomputes never move book orders. Without a limit the
+    /// order is priced at the band center (`last_center_price_1e6`) when it is placed, and
+    /// keeps that price. The crossing part trades immediately: the
+    /// input is pulled from the caller's token accounts and book proceeds are credited to
+    /// their OpenOrders for `settle_funds`. A resting remainder escrows its input token in
+    /// the pool vault (B for bids at the limit price, A for asks) and is tracked as locked.
+    /// Makers filled by this order are credited later by `consume_events`. The escrow is
+    /// always pulled from the ATA: free OpenOrders balance is only reused for escrow released
+    /// in the same instruction by `amend_order` or `place_orders_batch(cancel_existing)`.
+    #[allow(clippy::too_many_arguments)]
     pub fn place_order(
         ctx: Context<PlaceOrder>,
         side: Side,
         qty: u64,
         limit_price_opt_1e6: Option<u64>,
         tif: TifParam,
-        post_only: bool,
+        post_only: PostOnly,
         reduce_only: bool,
         client_id: u64,
     ) -> Result<u64> {
         // capture immutable info first
         let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
+        let oo = &mut ctx.accounts.open_orders;
+        if oo.owner == Pubkey::default() {
+            oo.bump = ctx.bumps.open_orders;
+            oo.pool = pool_key;
+            oo.owner = user_key;
+        }
+
+        let r = OrderRequest { side, qty, limit_price_opt_1e6, tif, post_only, reduce_only, client_id };
+        let mut flows = OrderFlows::default();
+        let order_id = new_order(ctx.accounts, pool_key, user_key, &r, &mut flows)?;
+        refresh_top_of_book(&ctx.accounts.orderbook, &mut ctx.accounts.pool)?;
+        settle_order_flows(ctx.accounts, &flows)?;
+        Ok(order_id)
+    }
 
+    /// Amends one of the caller's resting orders. Cutting the size at the same price (or to
+    /// zero) happens in place and keeps queue priority; anything else cancels the order and
+    /// places its replacement atomically, funded first by the released escrow. Returns the
+    /// id the order now rests under (0 if nothing rests).
+    pub fn amend_order(
+        ctx: Context<PlaceOrder>,
+        order: OrderRef,
+        new_qty: u64,
+        new_price_opt_1e6: Option<u64>,
+        post_only: PostOnly,
+    ) -> Result<u64> {
+        let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
         let pool = &mut ctx.accounts.pool;
         let ob = &mut ctx.accounts.orderbook;
+        let oo = &mut ctx.accounts.open_orders;
         require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
-        require!(qty > 0, DlmmError::ZeroAmount);
 
-        if Clock::get()?.slot <= pool.post_only_until_slot {
-            require!(post_only, DlmmError::Paused);
+        let idx = find_owned_order(ob, &user_key, order)?;
+        let o = ob.orders[idx as usize];
+        let now = Clock::get()?.slot;
+        require!(!is_expired(&o, now), DlmmError::Expired);
+        let new_price = new_price_opt_1e6.map_or(o.price_1e6, |p| round_to_tick(p, ob.tick_1e6));
+
+        require!(new_qty == 0 || new_qty >= ob.min_order_qty, DlmmError::OrderTooSmall);
+        if new_qty == 0 || (new_price == o.price_1e6 && new_qty <= o.qty) {
+            let released = fill_order(ob, idx, o.qty - new_qty)?;
+            match o.side {
+                Side::Bid => MakerCredit { owner: user_key, unlock_b: released, free_b: released, ..MakerCredit::default() },
+                Side::Ask => MakerCredit { owner: user_key, unlock_a: released, free_a: released, ..MakerCredit::default() },
+            }
+            .apply(oo)?;
+            refresh_top_of_book(ob, pool)?;
+            if new_qty == 0 {
+                emit!(OrderCanceledV3 {
+                    event_version: EVENT_VERSION,
+                    pool: pool_key,
+                    order_id: o.order_id,
+                    owner: user_key,
+                    side: o.side
+                });
+                return Ok(0);
+            }
+            emit!(OrderAmendedV {
+                event_version: EVENT_VERSION,
+                pool: pool_key,
+                order_id: o.order_id,
+                owner: user_key,
+                side: o.side,
+                price_1e6: o.price_1e6,
+                qty: new_qty,
+            });
+            return Ok(o.order_id);
+        }
+
+        // cancel-replace: the replacement goes to the back of its new level
+        cancel_owned(ob, oo, pool_key, idx)?;
+        let mut flows = OrderFlows::default();
+        match o.side {
+            Side::Bid => flows.released[1] = o.escrow,
+            Side::Ask => flows.released[0] = o.escrow,
         }
 
-        let price_1e6 = match limit_price_opt_1e6 {
-            Some(p) => round_to_tick(p, ob.tick_1e6),
-            None => pool.last_center_price_1e6,
+        let tif = match o.expiry_slot {
+            u64::MAX => TifParam { kind: 1, gtt_expiry_slot: 0 },
+            slot => TifParam { kind: 2, gtt_expiry_slot: slot },
+        };
+        let r = OrderRequest {
+            side: o.side,
+            qty: new_qty,
+            limit_price_opt_1e6: Some(new_price),
+            tif,
+            post_only,
+            reduce_only: o.reduce_only,
+            client_id: o.client_id,
         };
-        let target_band = map_price_to_band(pool, price_1e6)?;
+        let order_id = new_order(ctx.accounts, pool_key, user_key, &r, &mut flows)?;
+        refresh_top_of_book(&ctx.accounts.orderbook, &mut ctx.accounts.pool)?;
+        settle_order_flows(ctx.accounts, &flows)?;
+        Ok(order_id)
+    }
+
+    /// Places up to `MAX_BATCH_ORDERS` orders in one instruction, e.g. a quote ladder across
+    /// tick levels. With `cancel_existing` the caller's resting orders are cancelled first and their
+    /// released escrow funds the new ones. Token movements are netted and settled once and
+    /// the top of book is refreshed once. Returns the resting order id of each entry (0 if
+    /// it did not rest).
+    pub fn place_orders_batch(
+        ctx: Context<PlaceOrder>,
+        orders: Vec<BatchOrder>,
+        post_only: PostOnly,
+        cancel_existing: bool,
+    ) -> Result<Vec<u64>> {
+        require!(!orders.is_empty() && orders.len() <= MAX_BATCH_ORDERS, DlmmError::ParamOutOfRange);
+        let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
+        let oo = &mut ctx.accounts.open_orders;
+        if oo.owner == Pubkey::default() {
+            oo.bump = ctx.bumps.open_orders;
+            oo.pool = pool_key;
+            oo.owner = user_key;
+        }
 
-        let mut remaining = qty;
-        let mut order_id = 0u64;
+        let mut flows = OrderFlows::default();
+        if cancel_existing {
+            let pool = &mut ctx.accounts.pool;
+            let ob = &mut ctx.accounts.orderbook;
+            let oo = &mut ctx.accounts.open_orders;
+            require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
+            let (free_a, free_b) = (oo.base_free, oo.quote_free);
+            cancel_all_owned(ob, oo, pool_key, &user_key, None)?;
+            flows.released = [oo.base_free - free_a, oo.quote_free - free_b];
+        }
 
-        let stp = StpMode::from_u8(pool.stp_mode);
-        let route_mode = RouteMode::from_u8(pool.route_mode);
-        match route_mode {
-            RouteMode::BookFirst => {
-                if !post_only {
-                    remaining = match_against_book(
-                        ob,
-                        pool,
-                        pool_key,
-                        side.opposite(),
-                        price_1e6,
-                        remaining,
-                        &ctx.accounts.user.key(),
-                        stp,
-                    )?;
-                }
-                if remaining > 0 {
-                    if tif.is_ioc() || post_only {
-                        if post_only {
-                            order_id = rest_in_book(
-                                ob,
-                                side,
-                                target_band,
-                                remaining,
-                                tif,
-                                reduce_only,
-                                client_id,
-                                ctx.accounts.user.key(),
-                            )?;
-                            emit!(OrderPlacedV3 {
-                                event_version: EVENT_VERSION,
-                                pool: pool_key,
-                                order_id,
-                                owner: ctx.accounts.user.key(),
-                                side,
-                                price_1e6,
-                                qty: remaining,
-                                band_idx: target_band as i16
-                            });
-                        }
-                    } else {
-                        order_id = rest_in_book(
-                            ob,
-                            side,
-                            target_band,
-                            remaining,
-                            tif,
-                            reduce_only,
-                            client_id,
-                            ctx.accounts.user.key(),
-                        )?;
-                        emit!(OrderPlacedV3 {
-                            event_version: EVENT_VERSION,
-                            pool: pool_key,
-                            order_id,
-                            owner: ctx.accounts.user.key(),
-                            side,
-                            price_1e6,
-                            qty: remaining,
-                            band_idx: target_band as i16
-                        });
-                    }
-                }
-            }
-            RouteMode::DlmmFirst => {
-                if !post_only {
-                    remaining = take_from_bands(pool, pool_key, side, remaining, price_1e6)?;
-                }
-                if remaining > 0 {
-                    if tif.is_ioc() || post_only {
-                        if post_only {
-                            order_id = rest_in_book(
-                                ob,
-                                side,
-                                target_band,
-                                remaining,
-                                tif,
-                                reduce_only,
-                                client_id,
-                                ctx.accounts.user.key(),
-                            )?;
-                            emit!(OrderPlacedV3 {
-                                event_version: EVENT_VERSION,
-                                pool: pool_key,
-                                order_id,
-                                owner: ctx.accounts.user.key(),
-                                side,
-                                price_1e6,
-                                qty: remaining,
-                                band_idx: target_band as i16
-                            });
-                        }
-                    } else {
-                        order_id = rest_in_book(
-                            ob,
-                            side,
-                            target_band,
-                            remaining,
-                            tif,
-                            reduce_only,
-                            client_id,
-                            ctx.accounts.user.key(),
-                        )?;
-                        emit!(OrderPlacedV3 {
-                            event_version: EVENT_VERSION,
-                            pool: pool_key,
-                            order_id,
-                            owner: ctx.accounts.user.key(),
-                            side,
-                            price_1e6,
-                            qty: remaining,
-                            band_idx: target_band as i16
-                        });
-                    }
-                }
-            }
+        let mut ids = Vec::with_capacity(orders.len());
+        for o in orders.iter() {
+            let r = OrderRequest {
+                side: o.side,
+                qty: o.qty,
+                limit_price_opt_1e6: Some(o.price_1e6),
+                tif: o.tif,
+                post_only,
+                reduce_only: false,
+                client_id: o.client_id,
+            };
+            ids.push(new_order(ctx.accounts, pool_key, user_key, &r, &mut flows)?);
         }
+        refresh_top_of_book(&ctx.accounts.orderbook, &mut ctx.accounts.pool)?;
+        settle_order_flows(ctx.accounts, &flows)?;
+        Ok(ids)
+    }
+
+    /// Cancels one of the caller's resting orders; its remaining escrow moves from locked to
+    /// free in their OpenOrders and is paid out by `settle_funds`.
+    pub fn cancel_order(ctx: Context<CancelOrder>, side: Side, order_id: u64) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
+        let pool = &mut ctx.accounts.pool;
+        let ob = &mut ctx.accounts.orderbook;
+        let oo = &mut ctx.accounts.open_orders;
+        require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
 
+        let idx = find_owned_order(ob, &user_key, OrderRef::Id(order_id))?;
+        require!(ob.orders[idx as usize].side == side, DlmmError::NotFound);
+        cancel_owned(ob, oo, pool_key, idx)?;
         refresh_top_of_book(ob, pool)?;
-        Ok(order_id)
+        Ok(())
     }
 
-    pub fn cancel_order(ctx: Context<MutateOrderbook>, side: Side, order_id: u64) -> Result<()> {
+    /// Cancels every resting order of the caller tagged with `client_id`. Returns how many
+    /// were cancelled; fails with `NotFound` if none were.
+    pub fn cancel_by_client_id(ctx: Context<CancelOrder>, client_id: u64) -> Result<u16> {
         let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
         let pool = &mut ctx.accounts.pool;
         let ob = &mut ctx.accounts.orderbook;
+        let oo = &mut ctx.accounts.open_orders;
         require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
 
-        let removed = remove_order_linear(ob, side, order_id, Some(ctx.accounts.user.key()))?;
-        require!(removed, DlmmError::NotFound);
+        let mut canceled: u16 = 0;
+        while let Ok(idx) = find_owned_order(ob, &user_key, OrderRef::ClientId(client_id)) {
+            cancel_owned(ob, oo, pool_key, idx)?;
+            canceled += 1;
+        }
+        require!(canceled > 0, DlmmError::NotFound);
         refresh_top_of_book(ob, pool)?;
-        emit!(OrderCanceledV3 {
+        Ok(canceled)
+    }
+
+    /// Cancels all of the caller's resting orders, or only those on `side`. Returns how many
+    /// were cancelled; their escrow moves from locked to free in one go.
+    pub fn cancel_all_orders(ctx: Context<CancelOrder>, side: Option<Side>) -> Result<u16> {
+        let pool_key = ctx.accounts.pool.key();
+        let user_key = ctx.accounts.user.key();
+        let pool = &mut ctx.accounts.pool;
+        let ob = &mut ctx.accounts.orderbook;
+        let oo = &mut ctx.accounts.open_orders;
+        require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
+
+        let canceled = cancel_all_owned(ob, oo, pool_key, &user_key, side)?;
+        refresh_top_of_book(ob, pool)?;
+        Ok(canceled)
+    }
+
+    /// Pays the caller's free OpenOrders balances (fill proceeds, rebates and cancelled
+    /// escrow) from the vaults to their token accounts.
+    pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let ob = &mut ctx.accounts.orderbook;
+        let oo = &mut ctx.accounts.open_orders;
+        let (amount_a, amount_b) = (oo.base_free, oo.quote_free);
+        require!(amount_a > 0 || amount_b > 0, DlmmError::ZeroAmount);
+        oo.base_free = 0;
+        oo.quote_free = 0;
+        escrow_debit(&mut ob.escrow_a, amount_a)?;
+        escrow_debit(&mut ob.escrow_b, amount_b)?;
+
+        let pool: &Pool = &ctx.accounts.pool;
+        let seeds = pool_signer_seeds(pool);
+        let signer = &[&seeds[..]];
+        for (from, to, amount) in [
+            (ctx.accounts.vault_a.to_account_info(), ctx.accounts.user_ata_a.to_account_info(), amount_a),
+            (ctx.accounts.vault_b.to_account_info(), ctx.accounts.user_ata_b.to_account_info(), amount_b),
+        ] {
+            if amount > 0 {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer { from, to, authority: ctx.accounts.pool.to_account_info() },
+                        signer,
+                    ),
+                    amount,
+                )?;
+            }
+        }
+
+        emit!(FundsSettledV {
             event_version: EVENT_VERSION,
             pool: pool_key,
-            order_id,
             owner: ctx.accounts.user.key(),
-            side
+            amount_a,
+            amount_b
         });
         Ok(())
     }
 
+    /// Permissionless crank: applies up to `limit` queued events, oldest first, to the
+    /// makers' OpenOrders passed as remaining accounts. Stops early at the first event whose
+    /// owner's account was not passed. Returns the number consumed.
+    pub fn consume_events(ctx: Context<ConsumeEvents>, limit: u16) -> Result<u16> {
+        let pool_key = ctx.accounts.pool.key();
+        let ob = &mut ctx.accounts.orderbook;
+        let accounts = ctx.remaining_accounts;
+
+        let mut loaded: Vec<(usize, OpenOrders)> = Vec::new();
+        let mut consumed = 0u16;
+        while consumed < limit && event_q_len(ob) > 0 {
+            let head = ob.event_q_head as usize;
+            let credit = MakerCredit::from_event(&ob.event_q[head]);
+            let slot = match loaded.iter().position(|(_, oo)| oo.owner == credit.owner) {
+                Some(k) => k,
+                None => match find_open_orders(accounts, pool_key, credit.owner)? {
+                    Some(found) => {
+                        loaded.push(found);
+                        loaded.len() - 1
+                    }
+                    None => break,
+                },
+            };
+            credit.apply(&mut loaded[slot].1)?;
+            ob.event_q[head] = BookEvent::default();
+            ob.event_q_head = ((head + 1) % ob.event_q.len()) as u16;
+            consumed += 1;
+        }
+
+        for (i, oo) in loaded.iter() {
+            let mut data = accounts[*i].try_borrow_mut_data()?;
+            oo.try_serialize(&mut &mut data[..])?;
+        }
+        emit!(EventsConsumedV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            consumed,
+            remaining: event_q_len(ob) as u16
+        });
+        Ok(consumed)
+    }
+
+    /// Crosses resting bids and asks; both makers are credited through `consume_events`.
     pub fn crank_match(ctx: Context<MutateOrderbook>, max_iterations: u16) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
         let pool = &mut ctx.accounts.pool;
         let ob = &mut ctx.accounts.orderbook;
         require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
 
+        refresh_top_of_book(ob, pool)?;
+        let stp = StpMode::from_u8(pool.stp_mode);
+        // each iteration fully fills (or self-trade cancels) at least one head order
         let mut iters = 0u16;
         while iters < max_iterations {
-            let (bb, ba) = (ob.best_bid_band, ob.best_ask_band);
-            if bb < 0 || ba < 0 || (bb as i32) < (ba as i32) {
-                break;
-            }
-            let took = cross_once(ob, pool)?;
+            let took = cross_once(ob, pool_key, stp)?;
             if !took {
                 break;
             }
+            refresh_top_of_book(ob, pool)?;
             iters = iters.saturating_add(1);
         }
         refresh_top_of_book(ob, pool)?;
@@ -1030,44 +1904,31 @@ pub fn migrate_pool_versions(ctx: Context<AdminScoped>) -> Result<()> {
         let now = Clock::get()?.slot;
         let mut pruned = 0u16;
 
-        for lv in ob.bids.iter_mut() {
-            pruned = pruned.saturating_add(prune_level(lv, now, max_to_prune - pruned));
-            if pruned >= max_to_prune { break; }
-        }
-        if pruned < max_to_prune {
-            for lv in ob.asks.iter_mut() {
-                pruned = pruned.saturating_add(prune_level(lv, now, max_to_prune - pruned));
-                if pruned >= max_to_prune { break; }
+        for side in [Side::Bid, Side::Ask] {
+            // emptied levels are removed, so walk a snapshot of the prices
+            let prices: Vec<u64> = match side {
+                Side::Bid => ob.bids.iter().map(|l| l.price_1e6).collect(),
+                Side::Ask => ob.asks.iter().map(|l| l.price_1e6).collect(),
+            };
+            for px in prices {
+                if pruned >= max_to_prune || event_q_free(ob) == 0 { break; }
+                pruned = pruned.saturating_add(prune_level(ob, side, px, now, max_to_prune - pruned)?);
             }
         }
         refresh_top_of_book(ob, pool)?;
         Ok(())
     }
 
+    /// Emits the best `levels` tick levels of each side, bids first, best price first.
     pub fn view_depth(ctx: Context<ViewOrderbook>, levels: u8) -> Result<()> {
-        let pool = &ctx.accounts.pool;
         let ob = &ctx.accounts.orderbook;
-        let n = pool.n_bands as usize;
-
+        let n = levels as usize;
         let mut out: Vec<DepthItem> = Vec::new();
-        let center = pool.last_center_price_1e6;
-        let mut collected = 0usize;
-
-        let mut idxs: Vec<usize> = (0..n).collect();
-        idxs.sort_by_key(|i| {
-            let b = &pool.bands[*i];
-            let mid = mid_price(b.lower_price_1e6, b.upper_price_1e6);
-            diff_abs(center, mid)
-        });
-
-        for i in idxs {
-            if collected >= levels as usize { break; }
-            let bid_qty = ob.bids.get(i).map(|l| l.total_qty).unwrap_or(0);
-            let ask_qty = ob.asks.get(i).map(|l| l.total_qty).unwrap_or(0);
-            if bid_qty == 0 && ask_qty == 0 { continue; }
-            let mid = mid_price(pool.bands[i].lower_price_1e6, pool.bands[i].upper_price_1e6);
-            out.push(DepthItem { price_1e6: mid, bid_qty, ask_qty, band_idx: i as u16 });
-            collected += 1;
+        for l in ob.bids.iter().take(n) {
+            out.push(DepthItem { price_1e6: l.price_1e6, bid_qty: l.total_qty, ask_qty: 0 });
+        }
+        for l in ob.asks.iter().take(n) {
+            out.push(DepthItem { price_1e6: l.price_1e6, bid_qty: 0, ask_qty: l.total_qty });
         }
 
         emit!(DepthSnapshotV {
@@ -1100,7 +1961,7 @@ fn preview_center_width(pool: &Pool) -> Result<(u64, u16)> {
     Ok((center, width_bps))
 }
 
-fn recompute_bands(pool: &mut Account<Pool>, enforce_cb: bool, weights_only: bool) -> Result<()> {
+fn recompute_bands(pool: &mut Pool, enforce_cb: bool, weights_only: bool) -> Result<()> {
     let (mut center, mut width_bps) = preview_center_width(pool)?;
 
     if enforce_cb {
@@ -1130,6 +1991,7 @@ fn recompute_bands(pool: &mut Account<Pool>, enforce_cb: bool, weights_only: boo
 
     let mut new_bands = pool.bands.clone();
     new_bands.resize(n, Band::default());
+    let mut next_id = pool.next_band_id;
 
     for i in 0..n {
         let idx = i as i32 - mid;
@@ -1151,11 +2013,16 @@ fn recompute_bands(pool: &mut Account<Pool>, enforce_cb: bool, weights_only: boo
 
         if i >= pool.bands.len() {
             new_bands[i].is_active = true;
+            new_bands[i].id = next_id;
+            next_id = next_id.checked_add(1).ok_or(DlmmError::MathOverflow)?;
         } else {
             let old = &pool.bands[i];
             new_bands[i].reserves_a = old.reserves_a;
             new_bands[i].reserves_b = old.reserves_b;
             new_bands[i].total_shares = old.total_shares;
+            new_bands[i].pol_shares = old.pol_shares;
+            new_bands[i].locked_shares = old.locked_shares;
+            new_bands[i].id = old.id;
             new_bands[i].fee_growth_a_1e18 = old.fee_growth_a_1e18;
             new_bands[i].fee_growth_b_1e18 = old.fee_growth_b_1e18;
             new_bands[i].util_a = old.util_a;
@@ -1205,6 +2072,7 @@ fn recompute_bands(pool: &mut Account<Pool>, enforce_cb: bool, weights_only: boo
     }
 
     pool.bands = new_bands;
+    pool.next_band_id = next_id;
     pool.last_width_bps = width_bps;
     pool.last_center_price_1e6 = center;
     pool.total_weight_bps = pool.bands.iter().map(|b| b.weight_bps as u32).sum::<u32>();
@@ -1219,20 +2087,12 @@ fn round_to_tick(p: u64, tick: u64) -> u64 {
     if tick == 0 { return p; }
     (p / tick) * tick
 }
-fn map_price_to_band(pool: &Pool, price: u64) -> Result<u16> {
-    let n = pool.n_bands as usize;
-    let mut best_i = 0usize;
-    let mut best_d = u64::MAX;
-    for i in 0..n {
-        let b = &pool.bands[i];
-        let mid = mid_price(b.lower_price_1e6, b.upper_price_1e6);
-        let d = diff_abs(price, mid);
-        if d < best_d {
-            best_d = d;
-            best_i = i;
-        }
-    }
-    Ok(best_i as u16)
+/// Current index of the band with `band_id`.
+fn band_index(pool: &Pool, band_id: u32) -> Result<usize> {
+    pool.bands[..pool.n_bands as usize]
+        .iter()
+        .position(|b| b.id == band_id)
+        .ok_or(error!(DlmmError::BandNotFound))
 }
 fn mid_price(a: u64, b: u64) -> u64 {
     (a / 2).saturating_add(b / 2)
@@ -1240,144 +2100,538 @@ fn mid_price(a: u64, b: u64) -> u64 {
 fn diff_abs(a: u64, b: u64) -> u64 {
     if a >= b { a - b } else { b - a }
 }
+fn base_to_quote(base: u64, price_1e6: u64, round_up: bool) -> u64 {
+    let num = u128::from(base) * u128::from(price_1e6);
+    let q = if round_up { num.div_ceil(1_000_000) } else { num / 1_000_000 };
+    q.min(u64::MAX as u128) as u64
+}
 
+/// Publishes the best bid and ask level prices on the pool (0 / `u64::MAX` when a side has
+/// none). Levels holding only expired orders are skipped, as nobody can trade them.
 fn refresh_top_of_book(ob: &OrderBook, pool: &mut Pool) -> Result<()> {
-    let mut bb: Option<u64> = None;
-    for (i, l) in ob.bids.iter().enumerate().rev() {
-        if l.total_qty > 0 {
-            let b = &pool.bands[i];
-            bb = Some(mid_price(b.lower_price_1e6, b.upper_price_1e6));
-            break;
+    let now = Clock::get()?.slot;
+    let best = |levels: &[PriceLevel]| levels.iter().find(|l| level_is_live(ob, l, now)).map(|l| l.price_1e6);
+    pool.best_bid_1e6 = best(&ob.bids).unwrap_or(0);
+    pool.best_ask_1e6 = best(&ob.asks).unwrap_or(u64::MAX);
+    Ok(())
+}
+
+/// Whether `level` queues at least one order that has not expired.
+fn level_is_live(ob: &OrderBook, level: &PriceLevel, now_slot: u64) -> bool {
+    let mut cur = level.head;
+    while cur != ORDER_NIL {
+        let o = &ob.orders[cur as usize];
+        if !is_expired(o, now_slot) {
+            return true;
         }
+        cur = o.next;
     }
-    let mut ba: Option<u64> = None;
-    for (i, l) in ob.asks.iter().enumerate() {
-        if l.total_qty > 0 {
-            let b = &pool.bands[i];
-            ba = Some(mid_price(b.lower_price_1e6, b.upper_price_1e6));
-            break;
-        }
+    false
+}
+
+/// Allocates a slab slot for `o`, reusing the free list first.
+fn alloc_order(ob: &mut OrderBook, o: Order) -> Result<u32> {
+    if ob.free_head != ORDER_NIL {
+        let idx = ob.free_head;
+        ob.free_head = ob.orders[idx as usize].next;
+        ob.orders[idx as usize] = o;
+        return Ok(idx);
     }
-    pool.best_bid_1e6 = bb.unwrap_or(0);
-    pool.best_ask_1e6 = ba.unwrap_or(u64::MAX);
-    Ok(())
+    require!(ob.orders.len() < MAX_ORDERS, DlmmError::OrderbookFull);
+    ob.orders.push(o);
+    Ok((ob.orders.len() - 1) as u32)
 }
 
-fn rest_in_book(
-    ob: &mut OrderBook,
-    side: Side,
-    band_idx: u16,
-    qty: u64,
-    tif: TifParam,
-    reduce_only: bool,
-    client_id: u64,
-    owner: Pubkey,
-) -> Result<u64> {
-    let level = match side {
-        Side::Bid => ob.bids.get_mut(band_idx as usize).ok_or(DlmmError::InvalidBandIndex)?,
-        Side::Ask => ob.asks.get_mut(band_idx as usize).ok_or(DlmmError::InvalidBandIndex)?,
-    };
-    let q_len = (level.tail - level.head) as usize;
-    require!(q_len < ob.max_queue_per_level as usize, DlmmError::ParamOutOfRange);
+/// Where the level at `price_1e6` sits on `side`: `Ok` if it exists, else `Err` with the
+/// position that keeps the side best-first (bids descending, asks ascending).
+fn level_search(ob: &OrderBook, side: Side, price_1e6: u64) -> std::result::Result<usize, usize> {
+    match side {
+        Side::Bid => ob.bids.binary_search_by(|l| price_1e6.cmp(&l.price_1e6)),
+        Side::Ask => ob.asks.binary_search_by(|l| l.price_1e6.cmp(&price_1e6)),
+    }
+}
 
-    let id = ob.next_order_id;
-    ob.next_order_id = ob.next_order_id.saturating_add(1);
-    let expiry = tif.as_expiry(Clock::get()?.slot);
+/// Position of the level at `price_1e6` on `side`.
+fn level_pos(ob: &OrderBook, side: Side, price_1e6: u64) -> Option<usize> {
+    level_search(ob, side, price_1e6).ok()
+}
 
-    level.total_qty = level.total_qty.saturating_add(qty);
-    level.tail = level.tail.saturating_add(1);
+fn slab_index(ob: &OrderBook, order_id: u64) -> Option<u32> {
+    if order_id == 0 {
+        return None;
+    }
+    ob.orders.iter().position(|o| o.order_id == order_id).map(|i| i as u32)
+}
 
-    push_event(
-        ob,
-        BookEvent::Place {
-            order_id: id,
-            side,
-            band_idx: band_idx as i16,
-            owner,
+/// Slab index of `owner`'s resting order named by `order`. A known id owned by someone
+/// else fails with `Unauthorized`; client ids are only looked up among `owner`'s orders.
+fn find_owned_order(ob: &OrderBook, owner: &Pubkey, order: OrderRef) -> Result<u32> {
+    match order {
+        OrderRef::Id(order_id) => {
+            let idx = slab_index(ob, order_id).ok_or(DlmmError::NotFound)?;
+            require!(ob.orders[idx as usize].owner == *owner, DlmmError::Unauthorized);
+            Ok(idx)
+        }
+        OrderRef::ClientId(client_id) => ob
+            .orders
+            .iter()
+            .position(|o| o.order_id != 0 && o.owner == *owner && o.client_id == client_id)
+            .map(|i| i as u32)
+            .ok_or_else(|| error!(DlmmError::NotFound)),
+    }
+}
+
+/// Cancels the owner's order at `idx`: unlinks it, moves its remaining escrow from locked to
+/// free in `oo` and emits `OrderCanceledV3`. The caller refreshes the top of book.
+fn cancel_owned(ob: &mut OrderBook, oo: &mut OpenOrders, pool_pk: Pubkey, idx: u32) -> Result<()> {
+    let o = unlink_order(ob, idx)?;
+    match o.side {
+        Side::Bid => MakerCredit { owner: o.owner, unlock_b: o.escrow, free_b: o.escrow, ..MakerCredit::default() },
+        Side::Ask => MakerCredit { owner: o.owner, unlock_a: o.escrow, free_a: o.escrow, ..MakerCredit::default() },
+    }
+    .apply(oo)?;
+    emit!(OrderCanceledV3 {
+        event_version: EVENT_VERSION,
+        pool: pool_pk,
+        order_id: o.order_id,
+        owner: o.owner,
+        side: o.side
+    });
+    Ok(())
+}
+
+/// Cancels all of `owner`'s resting orders (only those on `side` if given) with
+/// `cancel_owned` and returns how many there were.
+fn cancel_all_owned(
+    ob: &mut OrderBook,
+    oo: &mut OpenOrders,
+    pool_pk: Pubkey,
+    owner: &Pubkey,
+    side: Option<Side>,
+) -> Result<u16> {
+    let mut canceled: u16 = 0;
+    for idx in 0..ob.orders.len() {
+        let o = ob.orders[idx];
+        if o.order_id == 0 || o.owner != *owner || side.is_some_and(|s| s != o.side) {
+            continue;
+        }
+        cancel_owned(ob, oo, pool_pk, idx as u32)?;
+        canceled += 1;
+    }
+    Ok(canceled)
+}
+
+/// Unlinks the order at `idx` from its level's FIFO, frees the slot and returns the order.
+/// A level left without orders is removed.
+fn unlink_order(ob: &mut OrderBook, idx: u32) -> Result<Order> {
+    let o = ob.orders[idx as usize];
+    let li = level_pos(ob, o.side, o.price_1e6).ok_or(DlmmError::NotFound)?;
+    if o.prev != ORDER_NIL {
+        ob.orders[o.prev as usize].next = o.next;
+    }
+    if o.next != ORDER_NIL {
+        ob.orders[o.next as usize].prev = o.prev;
+    }
+    let level = match o.side {
+        Side::Bid => &mut ob.bids[li],
+        Side::Ask => &mut ob.asks[li],
+    };
+    if level.head == idx {
+        level.head = o.next;
+    }
+    if level.tail == idx {
+        level.tail = o.prev;
+    }
+    level.total_qty = level.total_qty.saturating_sub(o.qty);
+    if level.head == ORDER_NIL {
+        match o.side {
+            Side::Bid => ob.bids.remove(li),
+            Side::Ask => ob.asks.remove(li),
+        };
+    }
+
+    ob.orders[idx as usize] = Order { next: ob.free_head, ..Order::empty() };
+    ob.free_head = idx;
+    Ok(o)
+}
+
+/// Rests `qty` at the tail of the level at `price_1e6`, opening the level if it is new.
+/// `escrow` is the input-token amount the caller has moved into the vault for it (B for
+/// bids, A for asks).
+#[allow(clippy::too_many_arguments)]
+fn rest_in_book(
+    ob: &mut OrderBook,
+    side: Side,
+    price_1e6: u64,
+    qty: u64,
+    escrow: u64,
+    tif: TifParam,
+    reduce_only: bool,
+    client_id: u64,
+    owner: Pubkey,
+) -> Result<u64> {
+    let resting = ob.orders.iter().filter(|o| o.order_id != 0 && o.owner == owner).count();
+    require!(resting < MAX_ORDERS_PER_OWNER, DlmmError::TooManyOrders);
+    let li = match level_search(ob, side, price_1e6) {
+        Ok(li) => li,
+        Err(li) => {
+            let n_levels = match side {
+                Side::Bid => ob.bids.len(),
+                Side::Ask => ob.asks.len(),
+            };
+            require!(n_levels < ob.max_levels as usize, DlmmError::OrderbookFull);
+            let level = PriceLevel { price_1e6, ..PriceLevel::default() };
+            match side {
+                Side::Bid => ob.bids.insert(li, level),
+                Side::Ask => ob.asks.insert(li, level),
+            }
+            li
+        }
+    };
+    let head = match side {
+        Side::Bid => ob.bids[li].head,
+        Side::Ask => ob.asks[li].head,
+    };
+    let mut q_len = 0usize;
+    let mut cur = head;
+    while cur != ORDER_NIL {
+        q_len += 1;
+        cur = ob.orders[cur as usize].next;
+    }
+    require!(q_len < ob.max_queue_per_level as usize, DlmmError::ParamOutOfRange);
+
+    let id = ob.next_order_id;
+    ob.next_order_id = ob.next_order_id.saturating_add(1);
+    let expiry = tif.as_expiry(Clock::get()?.slot);
+
+    let level = match side {
+        Side::Bid => &ob.bids[li],
+        Side::Ask => &ob.asks[li],
+    };
+    let tail = level.tail;
+    let idx = alloc_order(
+        ob,
+        Order {
+            order_id: id,
+            owner,
+            side,
+            price_1e6,
             qty,
+            escrow,
             client_id,
-            tif_expiry: expiry,
+            expiry_slot: expiry,
             reduce_only,
+            prev: tail,
+            next: ORDER_NIL,
         },
-    );
+    )?;
+    if tail != ORDER_NIL {
+        ob.orders[tail as usize].next = idx;
+    }
+    let level = match side {
+        Side::Bid => &mut ob.bids[li],
+        Side::Ask => &mut ob.asks[li],
+    };
+    if level.head == ORDER_NIL {
+        level.head = idx;
+    }
+    level.tail = idx;
+    level.total_qty = level.total_qty.saturating_add(qty);
+    match side {
+        Side::Bid => escrow_credit(&mut ob.escrow_b, escrow)?,
+        Side::Ask => escrow_credit(&mut ob.escrow_a, escrow)?,
+    }
     Ok(id)
 }
 
-fn remove_order_linear(ob: &mut OrderBook, side: Side, order_id: u64, owner_opt: Option<Pubkey>) -> Result<bool> {
-    for i in (0..ob.event_q.len()).rev() {
-        match ob.event_q[i] {
-            BookEvent::Place {
-                order_id: oid,
-                side: s,
-                band_idx,
-                owner,
-                qty,
-                ..
-            } if oid == order_id && s == side && owner_opt.map(|o| o == owner).unwrap_or(true) => {
-                let lvl = match side {
-                    Side::Bid => ob.bids.get_mut(band_idx as usize),
-                    Side::Ask => ob.asks.get_mut(band_idx as usize),
-                };
-                if let Some(level) = lvl {
-                    if level.total_qty >= qty {
-                        level.total_qty -= qty;
-                    } else {
-                        level.total_qty = 0;
-                    }
-                    push_event(
-                        ob,
-                        BookEvent::Out {
-                            order_id,
-                            reason: OUT_REASON_CANCEL,
-                        },
-                    );
-                    return Ok(true);
-                }
-            }
-            _ => {}
-        }
+/// Best live resting price on `side`, skipping expired orders.
+fn best_order_price(ob: &OrderBook, side: Side, now_slot: u64) -> Option<u64> {
+    let live = ob
+        .orders
+        .iter()
+        .filter(|o| o.order_id != 0 && o.side == side && !is_expired(o, now_slot))
+        .map(|o| o.price_1e6);
+    match side {
+        Side::Bid => live.max(),
+        Side::Ask => live.min(),
     }
-    Ok(false)
 }
 
-fn cross_once(ob: &mut OrderBook, pool: &Pool) -> Result<bool> {
-    let mut bid_i: Option<usize> = None;
-    for (i, l) in ob.bids.iter().enumerate().rev() {
-        if l.total_qty > 0 {
-            bid_i = Some(i);
-            break;
-        }
+/// Best curve price the active bands offer a taker on `taker_side`: the lowest among bands
+/// holding A for bids, the highest among bands holding B for asks.
+fn best_band_price(pool: &Pool, taker_side: Side) -> Result<Option<u64>> {
+    let mut best: Option<u64> = None;
+    for b in pool.bands[..pool.n_bands as usize].iter().filter(|b| b.is_active) {
+        let Some(c) = BandCurve::from_band(b)? else { continue };
+        let px = c.price_1e6();
+        best = match taker_side {
+            Side::Bid if b.reserves_a > 0 => Some(best.map_or(px, |p| p.min(px))),
+            Side::Ask if b.reserves_b > 0 => Some(best.map_or(px, |p| p.max(px))),
+            _ => best,
+        };
     }
-    let mut ask_i: Option<usize> = None;
-    for (i, l) in ob.asks.iter().enumerate() {
-        if l.total_qty > 0 {
-            ask_i = Some(i);
-            break;
-        }
+    Ok(best)
+}
+
+/// Escrow a resting order of `qty` base at `price_1e6` needs, in its input token.
+fn escrow_for(side: Side, qty: u64, price_1e6: u64) -> u64 {
+    match side {
+        Side::Bid => base_to_quote(qty, price_1e6, true),
+        Side::Ask => qty,
+    }
+}
+
+/// Input-token escrow released when `take` of an order fills. The last fill releases all
+/// that is left so rounding never strands escrow.
+fn escrow_release(o: &Order, take: u64) -> u64 {
+    if take >= o.qty {
+        return o.escrow;
+    }
+    match o.side {
+        Side::Bid => base_to_quote(take, o.price_1e6, false).min(o.escrow),
+        Side::Ask => take.min(o.escrow),
+    }
+}
+
+/// Fills `take` of the order at `idx`, removing it once empty. Returns the escrow released.
+fn fill_order(ob: &mut OrderBook, idx: u32, take: u64) -> Result<u64> {
+    let o = ob.orders[idx as usize];
+    let released = escrow_release(&o, take);
+    if take >= o.qty {
+        unlink_order(ob, idx)?;
+    } else {
+        let li = level_pos(ob, o.side, o.price_1e6).ok_or(DlmmError::NotFound)?;
+        let level = match o.side {
+            Side::Bid => &mut ob.bids[li],
+            Side::Ask => &mut ob.asks[li],
+        };
+        level.total_qty = level.total_qty.saturating_sub(take);
+        let slot = &mut ob.orders[idx as usize];
+        slot.qty -= take;
+        slot.escrow -= released;
+    }
+    Ok(released)
+}
+
+/// Takes `qty` off the resting order at `idx` without trading, for self-trade prevention.
+/// The released escrow reaches the owner through an `Out` event; `OrderCanceledV3` is
+/// emitted once nothing is left of the order.
+fn stp_reduce(ob: &mut OrderBook, pool_pk: Pubkey, idx: u32, qty: u64) -> Result<()> {
+    let o = ob.orders[idx as usize];
+    let released = fill_order(ob, idx, qty)?;
+    push_event(
+        ob,
+        BookEvent::Out { order_id: o.order_id, owner: o.owner, side: o.side, reason: OUT_REASON_SELF_TRADE, released },
+    )?;
+    if qty >= o.qty {
+        emit!(OrderCanceledV3 {
+            event_version: EVENT_VERSION,
+            pool: pool_pk,
+            order_id: o.order_id,
+            owner: o.owner,
+            side: o.side
+        });
     }
-    let (bi, ai) = match (bid_i, ask_i) {
-        (Some(bi), Some(ai)) if bi >= ai => (bi, ai),
+    Ok(())
+}
+
+/// Crosses the head orders of the best bid and best ask levels for the full overlapping
+/// quantity. The order that rested first is the maker and sets the price. Both sides are
+/// makers, so everything stays in the vaults and reaches their OpenOrders through
+/// `consume_events`. When both belong to the same owner `stp` decides which is cut instead.
+/// Returns false once nothing crosses or the event queue has no room for the two fills.
+fn cross_once(ob: &mut OrderBook, pool_pk: Pubkey, stp: StpMode) -> Result<bool> {
+    let (b_idx, a_idx) = match (ob.bids.first(), ob.asks.first()) {
+        (Some(b), Some(a)) if b.price_1e6 >= a.price_1e6 => (b.head, a.head),
         _ => return Ok(false),
     };
-    let price_band = ai;
-    let band = &pool.bands[price_band];
-    let px = mid_price(band.lower_price_1e6, band.upper_price_1e6);
-
-    let lot = 1u64;
-    let b_lvl = &mut ob.bids[bi];
-    let a_lvl = &mut ob.asks[ai];
-    if b_lvl.total_qty == 0 || a_lvl.total_qty == 0 {
+    let (bid, ask) = (ob.orders[b_idx as usize], ob.orders[a_idx as usize]);
+    if event_q_free(ob) < 2 {
         return Ok(false);
     }
-    b_lvl.total_qty = b_lvl.total_qty.saturating_sub(lot);
-    a_lvl.total_qty = a_lvl.total_qty.saturating_sub(lot);
+    // an expired head never trades; clearing it lets the next order in line cross
+    let now = Clock::get()?.slot;
+    if is_expired(&bid, now) || is_expired(&ask, now) {
+        for (idx, o) in [(b_idx, bid), (a_idx, ask)] {
+            if is_expired(&o, now) {
+                expire_order(ob, idx)?;
+            }
+        }
+        return Ok(true);
+    }
+    if bid.owner == ask.owner {
+        let (older, newer) = if bid.order_id < ask.order_id { (b_idx, a_idx) } else { (a_idx, b_idx) };
+        match stp {
+            StpMode::None => {}
+            StpMode::DecrementAndCancel => {
+                let d = bid.qty.min(ask.qty);
+                stp_reduce(ob, pool_pk, b_idx, d)?;
+                stp_reduce(ob, pool_pk, a_idx, d)?;
+                return Ok(true);
+            }
+            StpMode::CancelNewest => {
+                stp_reduce(ob, pool_pk, newer, ob.orders[newer as usize].qty)?;
+                return Ok(true);
+            }
+            StpMode::CancelOldest => {
+                stp_reduce(ob, pool_pk, older, ob.orders[older as usize].qty)?;
+                return Ok(true);
+            }
+        }
+    }
 
-    push_event(ob, BookEvent::Fill { order_id: 0, qty: lot, price_1e6: px, side: Side::Bid });
-    push_event(ob, BookEvent::Fill { order_id: 0, qty: lot, price_1e6: px, side: Side::Ask });
+    let qty = bid.qty.min(ask.qty);
+    let px = if bid.order_id < ask.order_id { bid.price_1e6 } else { ask.price_1e6 };
+    let quote = base_to_quote(qty, px, false);
+    let bid_release = fill_order(ob, b_idx, qty)?;
+    let ask_release = fill_order(ob, a_idx, qty)?;
+
+    push_event(
+        ob,
+        BookEvent::Fill {
+            order_id: bid.order_id,
+            maker: bid.owner,
+            side: Side::Bid,
+            qty,
+            price_1e6: px,
+            quote,
+            released: bid_release,
+            rebate_b: bid_release.saturating_sub(quote),
+        },
+    )?;
+    push_event(
+        ob,
+        BookEvent::Fill {
+            order_id: ask.order_id,
+            maker: ask.owner,
+            side: Side::Ask,
+            qty,
+            price_1e6: px,
+            quote,
+            released: ask_release,
+            rebate_b: 0,
+        },
+    )?;
 
     Ok(true)
 }
 
+/// Taker totals from walking the book. `quote` is B at the makers' prices; `fee` is the
+/// taker fee on it (in B) and `rebate` the part of that fee credited to makers.
+#[derive(Default)]
+struct BookTake {
+    base: u64,
+    quote: u64,
+    fee: u64,
+    rebate: u64,
+    /// taker quantity removed by `DecrementAndCancel` without trading
+    decremented: u64,
+    /// self-trade prevention cancelled the rest of the taker order
+    taker_canceled: bool,
+}
+
+/// OpenOrders balance changes for one owner: escrow leaving `*_locked` and proceeds
+/// landing in `*_free`.
+#[derive(Clone, Copy, Default)]
+struct MakerCredit {
+    owner: Pubkey,
+    unlock_a: u64,
+    unlock_b: u64,
+    free_a: u64,
+    free_b: u64,
+}
+impl MakerCredit {
+    /// What a queued event owes its maker.
+    fn from_event(ev: &BookEvent) -> MakerCredit {
+        match *ev {
+            BookEvent::Fill { maker, side: Side::Ask, quote, released, rebate_b, .. } => {
+                MakerCredit { owner: maker, unlock_a: released, free_b: quote + rebate_b, ..MakerCredit::default() }
+            }
+            BookEvent::Fill { maker, side: Side::Bid, qty, released, rebate_b, .. } => {
+                MakerCredit { owner: maker, unlock_b: released, free_a: qty, free_b: rebate_b, ..MakerCredit::default() }
+            }
+            BookEvent::Out { owner, side: Side::Ask, released, .. } => {
+                MakerCredit { owner, unlock_a: released, free_a: released, ..MakerCredit::default() }
+            }
+            BookEvent::Out { owner, side: Side::Bid, released, .. } => {
+                MakerCredit { owner, unlock_b: released, free_b: released, ..MakerCredit::default() }
+            }
+        }
+    }
+    fn apply(&self, oo: &mut OpenOrders) -> Result<()> {
+        escrow_debit(&mut oo.base_locked, self.unlock_a)?;
+        escrow_debit(&mut oo.quote_locked, self.unlock_b)?;
+        escrow_credit(&mut oo.base_free, self.free_a)?;
+        escrow_credit(&mut oo.quote_free, self.free_b)
+    }
+}
+
+/// Takes `amount` off a book escrow total or an OpenOrders balance. Coming up short means
+/// the escrow accounting is broken, so it fails rather than clamping to zero.
+fn escrow_debit(balance: &mut u64, amount: u64) -> Result<()> {
+    *balance = balance.checked_sub(amount).ok_or(DlmmError::EscrowMismatch)?;
+    Ok(())
+}
+fn escrow_credit(balance: &mut u64, amount: u64) -> Result<()> {
+    *balance = balance.checked_add(amount).ok_or(DlmmError::MathOverflow)?;
+    Ok(())
+}
+
+/// Position in `accounts` of the writable OpenOrders belonging to `owner` on `pool`, loaded.
+fn find_open_orders(accounts: &[AccountInfo], pool: Pubkey, owner: Pubkey) -> Result<Option<(usize, OpenOrders)>> {
+    for (i, ai) in accounts.iter().enumerate() {
+        if ai.owner != &crate::ID || !ai.is_writable {
+            continue;
+        }
+        let oo = match OpenOrders::try_deserialize(&mut &ai.try_borrow_data()?[..]) {
+            Ok(oo) => oo,
+            Err(_) => continue,
+        };
+        if oo.pool == pool && oo.owner == owner {
+            return Ok(Some((i, oo)));
+        }
+    }
+    Ok(None)
+}
+
+/// Prices of the levels on `hit_side` a taker limited at `limit_price_1e6` can trade, best
+/// first. Levels holding only expired orders are left out.
+fn crossing_levels(ob: &OrderBook, hit_side: Side, limit_price_1e6: u64, now_slot: u64) -> Vec<u64> {
+    let levels = match hit_side {
+        Side::Ask => &ob.asks,
+        Side::Bid => &ob.bids,
+    };
+    levels
+        .iter()
+        .take_while(|l| match hit_side {
+            Side::Ask => l.price_1e6 <= limit_price_1e6,
+            Side::Bid => l.price_1e6 >= limit_price_1e6,
+        })
+        .filter(|l| level_is_live(ob, l, now_slot))
+        .map(|l| l.price_1e6)
+        .collect()
+}
+
+/// Band price at which a `side` taker does as well on the bands as on a book level at
+/// `px`, both net of their fee: band fees come off the gross input (`fee_on_gross`), book
+/// fees are charged on top of the quote. Bids may pay the bands up to the returned price,
+/// asks sell to them down to it.
+fn band_price_matching(side: Side, px: u64, band_bps: u16, book_bps: u16) -> u64 {
+    let (band_bps, book_bps) = (u128::from(band_bps.min(9_999)), u128::from(book_bps.min(9_999)));
+    let px = u128::from(px);
+    match side {
+        // px * (1 + book) >= q / (1 - band)
+        Side::Bid => (px * (10_000 + book_bps) * (10_000 - band_bps) / 100_000_000u128).min(u64::MAX as u128) as u64,
+        // q * (1 - band) >= px * (1 - book)
+        Side::Ask => (px * (10_000 - book_bps)).div_ceil(10_000 - band_bps) as u64,
+    }
+}
+
+/// Fills against resting orders on `hit_side`, best tick level first and FIFO within a
+/// level, at the level's price. Makers' escrow pays the taker; makers' proceeds stay in the
+/// vaults and are queued as `Fill` events for `consume_events`. Resting orders of the
+/// taker itself are handled per `stp` instead of filled. `emit_fills` is off for quotes.
+#[allow(clippy::too_many_arguments)]
 fn match_against_book(
     ob: &mut OrderBook,
     pool: &Pool,
@@ -1386,68 +2640,518 @@ fn match_against_book(
     limit_price_1e6: u64,
     mut qty: u64,
     taker: &Pubkey,
-    _stp: StpMode,
-) -> Result<u64> {
-    let n = pool.n_bands as usize;
-    let mut idxs: Vec<usize> = Vec::new();
+    stp: StpMode,
+    emit_fills: bool,
+) -> Result<BookTake> {
+    let now = Clock::get()?.slot;
+    // emptied levels are removed as we go, so walk by price
+    let prices = crossing_levels(ob, hit_side, limit_price_1e6, now);
+
+    let taker_bps = pool.fee_current_bps.max(pool.taker_min_bps);
+    let maker_rebate_bps = pool.maker_rebate_max_bps.min(taker_bps);
+    let mut out = BookTake::default();
+
+    for px in prices {
+        if qty == 0 || out.taker_canceled { break; }
+        let Some(li) = level_pos(ob, hit_side, px) else { continue };
+        let mut cur = match hit_side {
+            Side::Ask => ob.asks[li].head,
+            Side::Bid => ob.bids[li].head,
+        };
+        while cur != ORDER_NIL && qty > 0 && !out.taker_canceled {
+            let o = ob.orders[cur as usize];
+            let next = o.next;
+            // expired orders stay put for `prune_expired` so their refund is queued there
+            if is_expired(&o, now) {
+                cur = next;
+                continue;
+            }
+            if o.owner == *taker {
+                match stp {
+                    StpMode::None => {}
+                    StpMode::DecrementAndCancel => {
+                        let d = qty.min(o.qty);
+                        stp_reduce(ob, pool_pk, cur, d)?;
+                        qty -= d;
+                        out.decremented += d;
+                        out.taker_canceled = qty == 0;
+                        cur = next;
+                        continue;
+                    }
+                    StpMode::CancelNewest => {
+                        out.taker_canceled = true;
+                        break;
+                    }
+                    StpMode::CancelOldest => {
+                        stp_reduce(ob, pool_pk, cur, o.qty)?;
+                        cur = next;
+                        continue;
+                    }
+                }
+            }
 
-    match hit_side {
-        Side::Ask => {
-            for i in 0..n {
-                let mid = mid_price(pool.bands[i].lower_price_1e6, pool.bands[i].upper_price_1e6);
-                if mid <= limit_price_1e6 {
-                    idxs.push(i);
-                } else {
-                    break;
+            let take = qty.min(o.qty);
+            let released = fill_order(ob, cur, take)?;
+            // hitting asks the taker pays B (rounded up); hitting bids the bid's escrow pays
+            let quote = match hit_side {
+                Side::Ask => base_to_quote(take, o.price_1e6, true),
+                Side::Bid => released,
+            };
+            let fee = fee_on_gross(quote, taker_bps.min(9_999));
+            let rebate = (u128::from(quote) * u128::from(maker_rebate_bps) / 10_000u128) as u64;
+            let rebate = rebate.min(fee);
+            match hit_side {
+                Side::Ask => {
+                    escrow_debit(&mut ob.escrow_a, released)?;
+                    escrow_credit(&mut ob.escrow_b, quote + rebate)?;
+                }
+                Side::Bid => {
+                    escrow_debit(&mut ob.escrow_b, released)?;
+                    escrow_credit(&mut ob.escrow_b, rebate)?;
+                    escrow_credit(&mut ob.escrow_a, take)?;
                 }
             }
+
+            if emit_fills {
+                emit!(OrderFilledV3 {
+                    event_version: EVENT_VERSION,
+                    pool: pool_pk,
+                    taker: *taker,
+                    side: hit_side.opposite(),
+                    qty: take,
+                    price_1e6: o.price_1e6,
+                    taker_fee_bps: taker_bps,
+                    maker_rebate_bps
+                });
+            }
+            push_event(
+                ob,
+                BookEvent::Fill {
+                    order_id: o.order_id,
+                    maker: o.owner,
+                    side: o.side,
+                    qty: take,
+                    price_1e6: o.price_1e6,
+                    quote,
+                    released,
+                    rebate_b: rebate,
+                },
+            )?;
+
+            out.base += take;
+            out.quote += quote;
+            out.fee += fee;
+            out.rebate += rebate;
+            qty -= take;
+            cur = next;
         }
+    }
+
+    Ok(out)
+}
+
+/// Takes up to `qty` base from resting orders at prices no worse than `limit_price_1e6`.
+/// The taker's payment is added to `flows`; its proceeds are credited to `oo` and stay in
+/// the vault for `settle_funds`.
+#[allow(clippy::too_many_arguments)]
+fn book_leg(
+    ob: &mut OrderBook,
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user_key: &Pubkey,
+    side: Side,
+    limit_price_1e6: u64,
+    qty: u64,
+    oo: &mut OpenOrders,
+    flows: &mut OrderFlows,
+) -> Result<BookTake> {
+    let stp = StpMode::from_u8(pool.stp_mode);
+    let t = match_against_book(ob, pool, pool_key, side.opposite(), limit_price_1e6, qty, user_key, stp, !flows.quote_only)?;
+    if t.taker_canceled && !flows.quote_only {
+        // order_id 0: the incoming order never rested
+        emit!(OrderCanceledV3 {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            order_id: 0,
+            owner: *user_key,
+            side
+        });
+    }
+    // the book fee is always in B; makers' rebates stay in the vault for them,
+    // and the taker's proceeds wait in the vault for `settle_funds`
+    let protocol_fee = t.fee - t.rebate;
+    match side {
         Side::Bid => {
-            for i in (0..n).rev() {
-                let mid = mid_price(pool.bands[i].lower_price_1e6, pool.bands[i].upper_price_1e6);
-                if mid >= limit_price_1e6 {
-                    idxs.push(i);
-                } else {
-                    break;
-                }
+            flows.user_to_vault[1] += t.quote + t.rebate;
+            flows.user_to_treasury[1] += protocol_fee;
+            MakerCredit { owner: *user_key, free_a: t.base, ..MakerCredit::default() }.apply(oo)?;
+            escrow_credit(&mut ob.escrow_a, t.base)?;
+        }
+        Side::Ask => {
+            flows.user_to_vault[0] += t.base;
+            flows.vault_to_treasury[1] += protocol_fee;
+            MakerCredit { owner: *user_key, free_b: t.quote - t.fee, ..MakerCredit::default() }.apply(oo)?;
+            escrow_credit(&mut ob.escrow_b, t.quote - t.fee)?;
+        }
+    }
+    pool.protocol_fees_b = pool.protocol_fees_b.saturating_add(protocol_fee);
+    Ok(t)
+}
+
+/// Takes up to `qty` base from the bands at prices no worse than `limit_price_1e6`, adding
+/// the token movements to `flows`. Returns the base traded and the fee charged.
+fn band_leg(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user_key: &Pubkey,
+    side: Side,
+    limit_price_1e6: u64,
+    qty: u64,
+    flows: &mut OrderFlows,
+) -> Result<(u64, u64)> {
+    // qty is base: bids buy it out of the bands, asks sell it in
+    let (mode, k_in, k_out) = match side {
+        Side::Bid => (SwapMode::ExactOut, 1, 0),
+        Side::Ask => (SwapMode::ExactIn, 0, 1),
+    };
+    let take = take_from_bands(pool, side, qty, limit_price_1e6, mode)?;
+    if !flows.quote_only {
+        emit_band_fills(pool_key, user_key, side, &take);
+    }
+    flows.user_to_vault[k_in] += take.amount_in - take.fee_paid;
+    flows.user_to_treasury[k_in] += take.fee_paid;
+    flows.vault_to_user[k_out] += take.amount_out;
+    let base = match side {
+        Side::Bid => take.amount_out,
+        Side::Ask => take.amount_in,
+    };
+    Ok((base.min(qty), take.fee_paid))
+}
+
+/// What the taking part of one order traded on each venue. Book fees are in B, band fees in
+/// the taker's input token.
+#[derive(Default)]
+struct TakerFill {
+    book_base: u64,
+    band_base: u64,
+    book_fee: u64,
+    band_fee: u64,
+    /// base left to rest; 0 once self-trade prevention canceled the order
+    remaining: u64,
+}
+
+/// Takes up to `qty` base for a `side` taker limited at `price_1e6`, from the book and the
+/// bands in the order the pool's `route_mode` gives. Shared by `new_order` and `quote_swap`.
+#[allow(clippy::too_many_arguments)]
+fn route_taker(
+    ob: &mut OrderBook,
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user_key: &Pubkey,
+    side: Side,
+    price_1e6: u64,
+    qty: u64,
+    oo: &mut OpenOrders,
+    flows: &mut OrderFlows,
+) -> Result<TakerFill> {
+    let mut out = TakerFill { remaining: qty, ..TakerFill::default() };
+    match RouteMode::from_u8(pool.route_mode) {
+        RouteMode::BookFirst => book_route(ob, pool, pool_key, user_key, side, price_1e6, &mut out, oo, flows)?,
+        RouteMode::DlmmFirst => band_route(pool, pool_key, user_key, side, price_1e6, &mut out, flows)?,
+        RouteMode::BestPrice => {
+            // before each crossing book level the bands fill for as long as they are at
+            // least as good net of fees (bands go first on a tie); whatever is left after
+            // the book goes to the bands
+            let book_bps = pool.fee_current_bps.max(pool.taker_min_bps);
+            let now = Clock::get()?.slot;
+            for px in crossing_levels(ob, side.opposite(), price_1e6, now) {
+                if out.remaining == 0 { break; }
+                let band_px = band_price_matching(side, px, pool.fee_current_bps, book_bps);
+                let band_px = match side {
+                    Side::Bid => band_px.min(price_1e6),
+                    Side::Ask => band_px.max(price_1e6),
+                };
+                band_route(pool, pool_key, user_key, side, band_px, &mut out, flows)?;
+                if out.remaining == 0 { break; }
+                book_route(ob, pool, pool_key, user_key, side, px, &mut out, oo, flows)?;
+            }
+            if out.remaining > 0 {
+                band_route(pool, pool_key, user_key, side, price_1e6, &mut out, flows)?;
             }
         }
     }
+    Ok(out)
+}
 
-    for i in idxs {
-        if qty == 0 { break; }
-        let level = match hit_side {
-            Side::Ask => &mut ob.asks[i],
-            Side::Bid => &mut ob.bids[i],
+/// `book_leg` for the rest of `out`.
+#[allow(clippy::too_many_arguments)]
+fn book_route(
+    ob: &mut OrderBook,
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user_key: &Pubkey,
+    side: Side,
+    limit_price_1e6: u64,
+    out: &mut TakerFill,
+    oo: &mut OpenOrders,
+    flows: &mut OrderFlows,
+) -> Result<()> {
+    let t = book_leg(ob, pool, pool_key, user_key, side, limit_price_1e6, out.remaining, oo, flows)?;
+    out.remaining = if t.taker_canceled { 0 } else { out.remaining - t.base - t.decremented };
+    out.book_base += t.base;
+    out.book_fee += t.fee;
+    Ok(())
+}
+
+/// `band_leg` for the rest of `out`.
+fn band_route(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user_key: &Pubkey,
+    side: Side,
+    limit_price_1e6: u64,
+    out: &mut TakerFill,
+    flows: &mut OrderFlows,
+) -> Result<()> {
+    let (base, fee) = band_leg(pool, pool_key, user_key, side, limit_price_1e6, out.remaining, flows)?;
+    out.remaining -= base;
+    out.band_base += base;
+    out.band_fee += fee;
+    Ok(())
+}
+
+/// Limit price of a new order, tick-aligned: without a limit the order is priced at the band
+/// center; levels are keyed by tick.
+fn order_price(pool: &Pool, ob: &OrderBook, limit_price_opt_1e6: Option<u64>) -> Result<u64> {
+    let price_1e6 = round_to_tick(limit_price_opt_1e6.unwrap_or(pool.last_center_price_1e6), ob.tick_1e6);
+    require!(limit_price_opt_1e6.is_none() || price_1e6 > 0, DlmmError::ParamOutOfRange);
+    Ok(price_1e6)
+}
+
+/// Parameters of one new order, as taken by `place_order` and the replace leg of `amend_order`.
+#[derive(Clone, Copy)]
+struct OrderRequest {
+    side: Side,
+    qty: u64,
+    limit_price_opt_1e6: Option<u64>,
+    tif: TifParam,
+    post_only: PostOnly,
+    reduce_only: bool,
+    client_id: u64,
+}
+
+/// Matches and/or rests one order for `user_key`, accumulating its token movements in
+/// `flows` for the caller to settle. Returns the resting order id, or 0 if nothing rests.
+/// The caller refreshes the top of book once it is done with the book.
+fn new_order(
+    accts: &mut PlaceOrder,
+    pool_key: Pubkey,
+    user_key: Pubkey,
+    r: &OrderRequest,
+    flows: &mut OrderFlows,
+) -> Result<u64> {
+    let OrderRequest { side, qty, limit_price_opt_1e6, tif, post_only, reduce_only, client_id } = *r;
+    let pool = &mut accts.pool;
+    let ob = &mut accts.orderbook;
+    let oo = &mut accts.open_orders;
+    require!(!pool.pause_orderbook && !pool.is_paused, DlmmError::Paused);
+    require!(qty > 0, DlmmError::ZeroAmount);
+    require!(qty >= ob.min_order_qty, DlmmError::OrderTooSmall);
+
+    let now = Clock::get()?.slot;
+    if now <= pool.post_only_until_slot {
+        require!(post_only != PostOnly::None, DlmmError::PostOnlyWindow);
+    }
+    require!(tif.as_expiry(now) >= now, DlmmError::Expired);
+    require!(!(tif.is_fok() && post_only != PostOnly::None), DlmmError::ParamOutOfRange);
+
+    let mut price_1e6 = order_price(pool, ob, limit_price_opt_1e6)?;
+    // a post-only order must not take from the book or the bands: reject it, or slide it one
+    // tick behind the better of the opposite book best and the best band price
+    if post_only != PostOnly::None {
+        let best = match (best_order_price(ob, side.opposite(), now), best_band_price(pool, side)?) {
+            (Some(o), Some(b)) => Some(match side {
+                Side::Bid => o.min(b),
+                Side::Ask => o.max(b),
+            }),
+            (o, b) => o.or(b),
         };
-        if level.total_qty == 0 { continue; }
+        if let Some(best) = best {
+            let crosses = match side {
+                Side::Bid => price_1e6 >= best,
+                Side::Ask => price_1e6 <= best,
+            };
+            if crosses {
+                require!(post_only == PostOnly::Slide, DlmmError::WouldCross);
+                // band prices need not sit on a tick: take the nearest tick strictly behind
+                price_1e6 = match side {
+                    Side::Bid => round_to_tick(best.saturating_sub(1), ob.tick_1e6),
+                    Side::Ask => round_to_tick(best, ob.tick_1e6).saturating_add(ob.tick_1e6),
+                };
+                require!(price_1e6 > 0, DlmmError::ParamOutOfRange);
+            }
+        }
+    }
 
-        let take = qty.min(level.total_qty);
-        level.total_qty -= take;
+    let mut remaining = qty;
+    let mut order_id = 0u64;
+    // [A, B] slot of the taker's input token
+    let k_in = match side {
+        Side::Bid => 1,
+        Side::Ask => 0,
+    };
 
-        let price = mid_price(pool.bands[i].lower_price_1e6, pool.bands[i].upper_price_1e6);
-        let taker_bps = pool.fee_current_bps.max(pool.taker_min_bps);
-        let maker_rebate_bps = pool.maker_rebate_max_bps.min(taker_bps);
+    // the book / band split is reported by the `OrderFilledV3` / `SwapFilledV` fills
+    let mut filled = 0u64;
+    if post_only == PostOnly::None {
+        let t = route_taker(ob, pool, pool_key, &user_key, side, price_1e6, qty, oo, flows)?;
+        remaining = t.remaining;
+        filled = t.book_base + t.band_base;
+    }
+
+    // failing here reverts the fills above along with the rest of the transaction
+    if tif.is_fok() {
+        require!(filled >= qty, DlmmError::FillOrKill);
+    }
 
-        emit!(OrderFilledV3 {
+    // IOC remainders are dropped; post-only orders always rest
+    if remaining > 0 && (post_only != PostOnly::None || !tif.is_ioc()) {
+        let escrow = escrow_for(side, remaining, price_1e6);
+        order_id = rest_in_book(
+            ob,
+            side,
+            price_1e6,
+            remaining,
+            escrow,
+            tif,
+            reduce_only,
+            client_id,
+            user_key,
+        )?;
+        // escrow released by a cancel earlier in this instruction (amend, batch cancel) funds
+        // the escrow before the ATA; other free balance such as fill proceeds and rebates is
+        // left for `settle_funds`. It is already counted in `ob.escrow_*`, which
+        // `rest_in_book` just added to
+        let (free, counted) = match side {
+            Side::Bid => (&mut oo.quote_free, &mut ob.escrow_b),
+            Side::Ask => (&mut oo.base_free, &mut ob.escrow_a),
+        };
+        let from_free = escrow.min(*free).min(flows.released[k_in]);
+        flows.released[k_in] -= from_free;
+        *free -= from_free;
+        *counted -= from_free;
+        flows.user_to_vault[k_in] += escrow - from_free;
+        match side {
+            Side::Bid => escrow_credit(&mut oo.quote_locked, escrow)?,
+            Side::Ask => escrow_credit(&mut oo.base_locked, escrow)?,
+        }
+        emit!(OrderPlacedV3 {
             event_version: EVENT_VERSION,
-            pool: pool_pk,
-            taker: *taker,
-            side: hit_side.opposite(),
-            qty: take,
-            price_1e6: price,
-            taker_fee_bps: taker_bps,
-            maker_rebate_bps
+            pool: pool_key,
+            order_id,
+            owner: user_key,
+            side,
+            price_1e6,
+            qty: remaining,
         });
+    }
+
+    Ok(order_id)
+}
+
+/// Token movements for one `place_order`, indexed `[A, B]`.
+#[derive(Default)]
+struct OrderFlows {
+    user_to_vault: [u64; 2],
+    user_to_treasury: [u64; 2],
+    vault_to_treasury: [u64; 2],
+    vault_to_user: [u64; 2],
+    /// escrow freed by cancels in this instruction that new orders may reuse instead of
+    /// pulling from the ATA; only amend and batch cancel set it
+    released: [u64; 2],
+    /// routing scratch copies for `quote_swap`: no fill events are emitted
+    quote_only: bool,
+}
+
+fn settle_order_flows(accts: &PlaceOrder, f: &OrderFlows) -> Result<()> {
+    let user = accts.user.to_account_info();
+    let pool_ai = accts.pool.to_account_info();
+    let tp = accts.token_program.to_account_info();
+    let user_ata = [accts.user_ata_a.to_account_info(), accts.user_ata_b.to_account_info()];
+    let vault = [accts.vault_a.to_account_info(), accts.vault_b.to_account_info()];
+    let treasury = [accts.treasury_a.to_account_info(), accts.treasury_b.to_account_info()];
+
+    for k in 0..2 {
+        for (to, amt) in [(&vault[k], f.user_to_vault[k]), (&treasury[k], f.user_to_treasury[k])] {
+            if amt > 0 {
+                token::transfer(
+                    CpiContext::new(
+                        tp.clone(),
+                        Transfer { from: user_ata[k].clone(), to: to.clone(), authority: user.clone() },
+                    ),
+                    amt,
+                )?;
+            }
+        }
+    }
 
-        qty -= take;
+    let pool: &Pool = &accts.pool;
+    let seeds = pool_signer_seeds(pool);
+    let signer = &[&seeds[..]];
+    for k in 0..2 {
+        for (to, amt) in [(&treasury[k], f.vault_to_treasury[k]), (&user_ata[k], f.vault_to_user[k])] {
+            if amt > 0 {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        tp.clone(),
+                        Transfer { from: vault[k].clone(), to: to.clone(), authority: pool_ai.clone() },
+                        signer,
+                    ),
+                    amt,
+                )?;
+            }
+        }
     }
 
-    Ok(qty)
+    assert_invariants(&accts.mint_a, &accts.mint_b, &accts.vault_a, &accts.vault_b, pool)
+}
+
+/// Result of walking band liquidity: input consumed and output paid, both in token units.
+/// `amount_in` includes `fee_paid`, which is charged in the input token.
+struct BandTake {
+    amount_in: u64,
+    amount_out: u64,
+    fee_paid: u64,
+    fee_bps: u16,
+    fills: Vec<BandFill>,
+}
+/// One band touched by a walk; `qty` is in base (token A) units.
+struct BandFill {
+    band_idx: u16,
+    qty: u64,
+    price_1e6: u64,
+}
+
+/// How the `amount` handed to `take_from_bands` is denominated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SwapMode {
+    /// `amount` is the taker's input token; spend up to it.
+    ExactIn,
+    /// `amount` is the taker's output token; reverse-solve the input needed per band.
+    ExactOut,
 }
 
 /* Fixed borrow: copy fee bps before mutable borrow of pool.bands[i] */
-fn take_from_bands(pool: &mut Account<Pool>, pool_pk: Pubkey, side: Side, mut qty: u64, limit_price_1e6: u64) -> Result<u64> {
+/// Walks bands nearest the center first, filling each at its price until `amount` is
+/// exhausted. Input is token B for `Side::Bid` and token A for `Side::Ask`. Callers emit
+/// the fills (see `emit_band_fills`) so the walk can also be run on a scratch copy.
+fn take_from_bands(
+    pool: &mut Pool,
+    side: Side,
+    amount: u64,
+    limit_price_1e6: u64,
+    mode: SwapMode,
+) -> Result<BandTake> {
     let n = pool.n_bands as usize;
     let mut idxs: Vec<usize> = (0..n).collect();
     let center = pool.last_center_price_1e6;
@@ -1458,66 +3162,396 @@ fn take_from_bands(pool: &mut Account<Pool>, pool_pk: Pubkey, side: Side, mut qt
     });
 
     // read fee/current bps into locals BEFORE taking mutable borrows into bands
-    let fee_current_bps_u128: u128 = pool.fee_current_bps as u128;
-    let fee_current_bps_u16: u16 = pool.fee_current_bps;
-
+    let fee_current_bps_u16: u16 = pool.fee_current_bps.min(9_999);
+    let protocol_fee_bps: u16 = pool.protocol_fee_bps.min(10_000);
+    let mut protocol_fee = 0u64;
+
+    let mut out = BandTake {
+        amount_in: 0,
+        amount_out: 0,
+        fee_paid: 0,
+        fee_bps: fee_current_bps_u16,
+        fills: Vec::new(),
+    };
+    let mut left = amount;
     for i in idxs {
-        if qty == 0 { break; }
+        if left == 0 { break; }
         let b = &mut pool.bands[i];
         if !b.is_active { continue; }
-        let mid = mid_price(b.lower_price_1e6, b.upper_price_1e6);
-        match side {
-            Side::Bid => { if mid > limit_price_1e6 { continue; } }
-            Side::Ask => { if mid < limit_price_1e6 { continue; } }
-        }
 
-        let cap = match side {
-            Side::Bid => b.reserves_b,
-            Side::Ask => b.reserves_a,
+        // exact-in budgets are gross of the fee; the curve only ever sees the net input
+        let budget = match mode {
+            SwapMode::ExactIn => left.saturating_sub(fee_on_gross(left, fee_current_bps_u16)),
+            SwapMode::ExactOut => left,
+        };
+
+        // (net input used, output paid) along the band's curve, stopping at the limit price
+        // a band too large to price or fill is skipped rather than failing the walk
+        let (used, paid) = match BandCurve::from_band(b)? {
+            Some(c) => match c.fill(b, side, mode, budget, limit_price_1e6) {
+                Ok(v) => v,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        if paid == 0 || used == 0 { continue; }
+        let gross = if mode == SwapMode::ExactIn && used == budget {
+            left
+        } else {
+            gross_of_net(used, fee_current_bps_u16)
+        };
+        let fee = gross.saturating_sub(used);
+        let (trade, quote) = match side {
+            Side::Bid => (paid, used),
+            Side::Ask => (used, paid),
         };
-        if cap == 0 { continue; }
 
-        let trade = qty.min(cap);
         match side {
-            Side::Bid => { b.reserves_b = b.reserves_b.saturating_sub(trade); b.reserves_a = b.reserves_a.saturating_add(trade); }
-            Side::Ask => { b.reserves_a = b.reserves_a.saturating_sub(trade); b.reserves_b = b.reserves_b.saturating_add(trade); }
+            Side::Bid => { b.reserves_a = b.reserves_a.saturating_sub(paid); b.reserves_b = b.reserves_b.saturating_add(used); }
+            Side::Ask => { b.reserves_b = b.reserves_b.saturating_sub(paid); b.reserves_a = b.reserves_a.saturating_add(used); }
+        }
+
+        // fee is paid in the input token and only grows that token's accumulator, per
+        // LP-held share; the protocol cut and the part earned by pool-owned and locked
+        // shares (the whole fee, if no LP holds shares) accrue to the protocol instead
+        let fee_to_shares = fee - (u128::from(fee) * u128::from(protocol_fee_bps) / 10_000u128) as u64;
+        let lp_shares = b.total_shares.saturating_sub(b.pol_shares).saturating_sub(b.locked_shares);
+        let lp_fee = if lp_shares == 0 {
+            0
+        } else {
+            (u128::from(fee_to_shares) * u128::from(lp_shares) / u128::from(b.total_shares)) as u64
+        };
+        protocol_fee = protocol_fee.saturating_add(fee - lp_fee);
+        if lp_fee > 0 {
+            let growth = u128::from(lp_fee) * 1_000_000_000_000_000_000u128 / u128::from(lp_shares);
+            match side {
+                Side::Bid => b.fee_growth_b_1e18 = b.fee_growth_b_1e18.saturating_add(growth),
+                Side::Ask => b.fee_growth_a_1e18 = b.fee_growth_a_1e18.saturating_add(growth),
+            }
         }
 
-        let fee_bps = fee_current_bps_u128;
-        let growth = (u128::from(trade) * fee_bps) * 1_000_000_000_000_000u128 / 10_000u128;
-        b.fee_growth_a_1e18 = b.fee_growth_a_1e18.saturating_add(growth);
-        b.fee_growth_b_1e18 = b.fee_growth_b_1e18.saturating_add(growth);
+        out.fills.push(BandFill {
+            band_idx: i as u16,
+            qty: trade,
+            price_1e6: (u128::from(quote) * 1_000_000u128 / u128::from(trade)) as u64,
+        });
+
+        left -= match mode {
+            SwapMode::ExactIn => gross,
+            SwapMode::ExactOut => paid,
+        };
+        out.amount_in = out.amount_in.saturating_add(gross);
+        out.fee_paid = out.fee_paid.saturating_add(fee);
+        out.amount_out = out.amount_out.saturating_add(paid);
+    }
+    match side {
+        Side::Bid => pool.protocol_fees_b = pool.protocol_fees_b.saturating_add(protocol_fee),
+        Side::Ask => pool.protocol_fees_a = pool.protocol_fees_a.saturating_add(protocol_fee),
+    }
+    Ok(out)
+}
 
+fn emit_band_fills(pool_pk: Pubkey, taker: &Pubkey, side: Side, take: &BandTake) {
+    for f in take.fills.iter() {
         emit!(SwapFilledV {
             event_version: EVENT_VERSION,
             pool: pool_pk,
+            taker: *taker,
             side,
-            qty: trade,
-            price_1e6: mid,
-            band_idx: i as u16,
-            fee_bps: fee_current_bps_u16
+            qty: f.qty,
+            price_1e6: f.price_1e6,
+            band_idx: f.band_idx,
+            fee_bps: take.fee_bps
         });
+    }
+}
+
+/* =============================================================================
+                                   Band curve
+============================================================================= */
+
+/// Constant-product segment spanning a band's `[lower, upper]` range. The band's real
+/// reserves are topped up with virtual reserves so that `x * y = L^2` holds and the
+/// price `y / x` runs from `lower` (all token A) to `upper` (all token B). Nothing is
+/// stored: the curve is rebuilt from reserves and range, so keeper range moves simply
+/// re-anchor it.
+struct BandCurve {
+    /// liquidity L
+    l: u128,
+    /// virtual reserves of A and B
+    x: u128,
+    y: u128,
+    /// sqrt(lower) and sqrt(upper), scaled by SQRT_Q
+    sa: u128,
+    sb: u128,
+}
+
+impl BandCurve {
+    /// `None` for an empty band, or one too large to price; callers skip it either way.
+    fn from_band(b: &Band) -> Result<Option<BandCurve>> {
+        if b.reserves_a == 0 && b.reserves_b == 0 {
+            return Ok(None);
+        }
+        let sa = sqrt_price_q(b.lower_price_1e6);
+        let sb = sqrt_price_q(b.upper_price_1e6);
+        require!(sa > 0 && sb > sa, DlmmError::InvalidBandRange);
+        let (rx, ry) = (u128::from(b.reserves_a), u128::from(b.reserves_b));
+
+        // L is linear in the reserves: solve it on reserves scaled below 2^36, where every
+        // intermediate fits in u128, and scale back
+        let shift = (128 - rx.max(ry).leading_zeros()).saturating_sub(36);
+        let Some(l) = curve_liquidity(rx >> shift, ry >> shift, sa, sb).and_then(|l| l.checked_mul(1u128 << shift))
+        else {
+            return Ok(None);
+        };
+        if l == 0 {
+            return Ok(None);
+        }
+
+        let (Some(vx), Some(vy)) = (l.checked_mul(SQRT_Q), l.checked_mul(sa)) else {
+            return Ok(None);
+        };
+        Ok(Some(BandCurve { l, x: rx + vx / sb, y: ry + vy / SQRT_Q, sa, sb }))
+    }
+
+    /// Marginal price `y / x` of the segment (B per A, 1e6 scale).
+    fn price_1e6(&self) -> u64 {
+        (self.y.saturating_mul(1_000_000) / self.x.max(1)).min(u64::MAX as u128) as u64
+    }
+
+    /// Returns (input used, output paid) for trading `left` against this band in `mode`,
+    /// without moving the price past `limit_price_1e6` or the band's own edge. Rounding
+    /// always favours the band.
+    fn fill(&self, b: &Band, side: Side, mode: SwapMode, left: u64, limit_price_1e6: u64) -> Result<(u64, u64)> {
+        // the invariant is taken from the virtual reserves themselves, so a rounded L can't
+        // open a gap between x * y and L^2 that the trade would pay out
+        let l2 = ck_mul(self.x, self.y)?;
+        let left = u128::from(left);
+        let s_lim = sqrt_price_q(limit_price_1e6);
+
+        let (used, paid) = match side {
+            // taker pays B, receives A: price rises towards min(upper, limit)
+            Side::Bid => {
+                let s_cap = self.sb.min(s_lim);
+                if s_cap == 0 {
+                    return Ok((0, 0));
+                }
+                let y_cap = ck_mul(self.l, s_cap)? / SQRT_Q;
+                let dy_max = y_cap.saturating_sub(self.y);
+                let dx_max = self
+                    .x
+                    .saturating_sub(ck_mul(self.l, SQRT_Q)?.div_ceil(s_cap))
+                    .min(u128::from(b.reserves_a));
+                match mode {
+                    SwapMode::ExactIn => {
+                        let used = left.min(dy_max);
+                        let x1 = l2.div_ceil(self.y + used);
+                        (used, self.x.saturating_sub(x1).min(u128::from(b.reserves_a)))
+                    }
+                    SwapMode::ExactOut => {
+                        let paid = left.min(dx_max);
+                        if paid == 0 || paid >= self.x {
+                            return Ok((0, 0));
+                        }
+                        let y1 = l2.div_ceil(self.x - paid);
+                        (y1.saturating_sub(self.y), paid)
+                    }
+                }
+            }
+            // taker pays A, receives B: price falls towards max(lower, limit)
+            Side::Ask => {
+                let s_cap = self.sa.max(s_lim);
+                let x_cap = ck_mul(self.l, SQRT_Q)? / s_cap;
+                let dx_max = x_cap.saturating_sub(self.x);
+                let dy_max = self
+                    .y
+                    .saturating_sub(ck_mul(self.l, s_cap)?.div_ceil(SQRT_Q))
+                    .min(u128::from(b.reserves_b));
+                match mode {
+                    SwapMode::ExactIn => {
+                        let used = left.min(dx_max);
+                        let y1 = l2.div_ceil(self.x + used);
+                        (used, self.y.saturating_sub(y1).min(u128::from(b.reserves_b)))
+                    }
+                    SwapMode::ExactOut => {
+                        let paid = left.min(dy_max);
+                        if paid == 0 || paid >= self.y {
+                            return Ok((0, 0));
+                        }
+                        let x1 = l2.div_ceil(self.y - paid);
+                        (x1.saturating_sub(self.x), paid)
+                    }
+                }
+            }
+        };
+        require!(used <= u64::MAX as u128, DlmmError::MathOverflow);
+        Ok((used as u64, paid as u64))
+    }
+}
+
+/// Liquidity L of a segment over `[sa, sb]` holding real reserves `(rx, ry)`, or `None` on
+/// overflow.
+fn curve_liquidity(rx: u128, ry: u128, sa: u128, sb: u128) -> Option<u128> {
+    let ds = sb - sa;
+    if ry == 0 {
+        // all A: x = L * ds * Q / (sa * sb)
+        (rx.checked_mul(sa)? / SQRT_Q).checked_mul(sb).map(|v| v / ds)
+    } else if rx == 0 {
+        // all B: y = L * ds / Q
+        ry.checked_mul(SQRT_Q).map(|v| v / ds)
+    } else {
+        // L = sb * (k + sqrt(k^2 + 4xy * ds / sb)) / (2 ds), with k = x*sa/Q + y*Q/sb
+        let k = (rx.checked_mul(sa)? / SQRT_Q).checked_add(ry.checked_mul(SQRT_Q)? / sb)?;
+        let disc = k.checked_mul(k)?.checked_add(rx.checked_mul(ry)?.checked_mul(4)?.checked_mul(ds)? / sb)?;
+        sb.checked_mul(k.checked_add(isqrt_u128(disc))?).map(|v| v / (2 * ds))
+    }
+}
+
+/// sqrt(price) scaled by SQRT_Q, from a 1e6-scaled price.
+fn sqrt_price_q(price_1e6: u64) -> u128 {
+    isqrt_u128(u128::from(price_1e6) * 1_000_000_000_000u128)
+}
+fn isqrt_u128(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = 1u128 << ((128 - n.leading_zeros()).div_ceil(2));
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+fn ck_mul(a: u128, b: u128) -> Result<u128> {
+    Ok(a.checked_mul(b).ok_or(DlmmError::MathOverflow)?)
+}
+
+/// Expiry and anti update-then-trade gate shared by the swap entrypoints.
+fn check_swap_window(pool: &Pool, expiry_slot: u64) -> Result<()> {
+    require!(!pool.is_paused, DlmmError::Paused);
+    let now = Clock::get()?.slot;
+    require!(now <= expiry_slot, DlmmError::Expired);
+    // same window during which the CLOB is post-only
+    require!(now > pool.post_only_until_slot, DlmmError::PostOnlyWindow);
+    Ok(())
+}
 
-        qty -= trade;
+/// A swap has no price limit of its own; slippage is bounded by the caller's amounts.
+fn swap_limit(side: Side) -> u64 {
+    match side {
+        Side::Bid => u64::MAX,
+        Side::Ask => 0,
     }
-    Ok(qty)
 }
 
-fn prune_level(level: &mut PriceLevel, _now_slot: u64, mut left: u16) -> u16 {
-    if left == 0 { return 0; }
+/// Moves the taker's input into its vault and pays the output from the opposite vault.
+/// The fee portion of the input goes to the input token's treasury, where `collect_fees` pays it from.
+fn settle_swap(accts: &Swap, side: Side, take: &BandTake) -> Result<()> {
+    let (user_in, vault_in, treasury_in, vault_out, user_out) = match side {
+        Side::Bid => (
+            accts.user_ata_b.to_account_info(),
+            accts.vault_b.to_account_info(),
+            accts.treasury_b.to_account_info(),
+            accts.vault_a.to_account_info(),
+            accts.user_ata_a.to_account_info(),
+        ),
+        Side::Ask => (
+            accts.user_ata_a.to_account_info(),
+            accts.vault_a.to_account_info(),
+            accts.treasury_a.to_account_info(),
+            accts.vault_b.to_account_info(),
+            accts.user_ata_b.to_account_info(),
+        ),
+    };
+
+    let net_in = take.amount_in.saturating_sub(take.fee_paid);
+    if net_in > 0 {
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                Transfer { from: user_in.clone(), to: vault_in, authority: accts.user.to_account_info() },
+            ),
+            net_in,
+        )?;
+    }
+    if take.fee_paid > 0 {
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                Transfer { from: user_in, to: treasury_in, authority: accts.user.to_account_info() },
+            ),
+            take.fee_paid,
+        )?;
+    }
+
+    let pool: &Pool = &accts.pool;
+    let seeds = pool_signer_seeds(pool);
+    let signer = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            accts.token_program.to_account_info(),
+            Transfer { from: vault_out, to: user_out, authority: accts.pool.to_account_info() },
+            signer,
+        ),
+        take.amount_out,
+    )?;
+
+    assert_invariants(&accts.mint_a, &accts.mint_b, &accts.vault_a, &accts.vault_b, pool)
+}
+
+/// Removes up to `left` expired orders from the level at `price_1e6`, anywhere in its
+/// queue, while the event queue has room for their `Out` events.
+fn prune_level(ob: &mut OrderBook, side: Side, price_1e6: u64, now_slot: u64, mut left: u16) -> Result<u16> {
     let mut pruned = 0u16;
-    while left > 0 && level.head < level.tail && level.total_qty == 0 {
-        level.head = level.head.saturating_add(1);
-        pruned = pruned.saturating_add(1);
-        left = left.saturating_sub(1);
+    let Some(li) = level_pos(ob, side, price_1e6) else { return Ok(0) };
+    let mut cur = match side {
+        Side::Bid => ob.bids[li].head,
+        Side::Ask => ob.asks[li].head,
+    };
+    while cur != ORDER_NIL && left > 0 && event_q_free(ob) > 0 {
+        let next = ob.orders[cur as usize].next;
+        if is_expired(&ob.orders[cur as usize], now_slot) {
+            expire_order(ob, cur)?;
+            pruned = pruned.saturating_add(1);
+            left -= 1;
+        }
+        cur = next;
     }
-    pruned
+    Ok(pruned)
+}
+
+/// GTT orders are good through their expiry slot; IOC and GTC never reach the book expired.
+fn is_expired(o: &Order, now_slot: u64) -> bool {
+    o.expiry_slot < now_slot
+}
+
+/// Takes an expired order off the book; its escrow reaches the owner through an `Out` event.
+fn expire_order(ob: &mut OrderBook, idx: u32) -> Result<()> {
+    let o = unlink_order(ob, idx)?;
+    push_event(
+        ob,
+        BookEvent::Out { order_id: o.order_id, owner: o.owner, side: o.side, reason: OUT_REASON_EXPIRED, released: o.escrow },
+    )
 }
 
-fn push_event(ob: &mut OrderBook, ev: BookEvent) {
-    let idx = (ob.event_q_head as usize) % ob.event_q.len();
-    ob.event_q[idx] = ev;
-    ob.event_q_head = ob.event_q_head.wrapping_add(1);
+/// Appends to the event ring. One slot stays empty so a full queue is distinguishable
+/// from an empty one; when full the instruction fails until `consume_events` catches up.
+fn push_event(ob: &mut OrderBook, ev: BookEvent) -> Result<()> {
+    let cap = ob.event_q.len();
+    let next = (ob.event_q_tail as usize + 1) % cap;
+    require!(next != ob.event_q_head as usize, DlmmError::EventQueueFull);
+    ob.event_q[ob.event_q_tail as usize] = ev;
+    ob.event_q_tail = next as u16;
+    Ok(())
+}
+fn event_q_len(ob: &OrderBook) -> usize {
+    let cap = ob.event_q.len();
+    (ob.event_q_tail as usize + cap - ob.event_q_head as usize) % cap
+}
+fn event_q_free(ob: &OrderBook) -> usize {
+    ob.event_q.len() - 1 - event_q_len(ob)
 }
 
 /* =============================================================================
@@ -1577,15 +3611,193 @@ fn passes_ratio_guard(pool: &Pool, a: u64, b: u64) -> Result<bool> {
         r_bps >= pool.deposit_ratio_min_bps as u64 && r_bps <= pool.deposit_ratio_max_bps as u64,
     )
 }
+fn range_weights(pool: &Pool, lower: u8, upper: u8, mode: DistributionMode, custom: &[u16]) -> Result<Vec<u64>> {
+    let bands = &pool.bands[lower as usize..=upper as usize];
+    if mode == DistributionMode::Custom {
+        require!(custom.len() == bands.len(), DlmmError::InvalidDistribution);
+        return Ok(custom.iter().map(|w| u64::from(*w)).collect());
+    }
+    Ok(bands
+        .iter()
+        .map(|b| match (b.is_active, mode) {
+            (false, _) => 0,
+            (true, DistributionMode::Weight) => u64::from(b.weight_bps),
+            (true, _) => 1,
+        })
+        .collect())
+}
+/// Brings every leg's fee checkpoints up to its band's growth and returns the fees owed,
+/// emitting `FeesCollectedV` per leg that earned any.
+fn accrue_range_fees(pool: &Pool, pool_key: Pubkey, pos: &mut RangePosition) -> Result<(u64, u64)> {
+    let owner = pos.owner;
+    let (mut owed_a, mut owed_b) = (0u64, 0u64);
+    for leg in pos.legs.iter_mut() {
+        if leg.shares == 0 { continue; }
+        let i = band_index(pool, leg.band_id)?;
+        let b = &pool.bands[i];
+        let leg_a = mul_div_1e18(leg.shares, b.fee_growth_a_1e18.saturating_sub(leg.last_fee_growth_a_1e18));
+        let leg_b = mul_div_1e18(leg.shares, b.fee_growth_b_1e18.saturating_sub(leg.last_fee_growth_b_1e18));
+        leg.last_fee_growth_a_1e18 = b.fee_growth_a_1e18;
+        leg.last_fee_growth_b_1e18 = b.fee_growth_b_1e18;
+        if leg_a == 0 && leg_b == 0 { continue; }
+        owed_a = owed_a.saturating_add(leg_a);
+        owed_b = owed_b.saturating_add(leg_b);
+
+        emit!(FeesCollectedV {
+            event_version: EVENT_VERSION,
+            pool: pool_key,
+            owner,
+            band_idx: i as u8,
+            out_a: leg_a,
+            out_b: leg_b
+        });
+    }
+    Ok((owed_a, owed_b))
+}
+/// Pays accrued range fees from the treasuries, where LP fees are held.
+fn pay_range_fees(accts: &WithRangePosition, owed_a: u64, owed_b: u64) -> Result<()> {
+    let pool_ai = accts.pool.to_account_info();
+    let seeds = pool_signer_seeds(&accts.pool);
+    let signer = &[&seeds[..]];
+
+    if owed_a > 0 {
+        token::transfer(
+            CpiContext::new_with_signer(
+                accts.token_program.to_account_info(),
+                Transfer {
+                    from: accts.treasury_a.to_account_info(),
+                    to: accts.user_ata_a.to_account_info(),
+                    authority: pool_ai.clone(),
+                },
+                signer,
+            ),
+            owed_a,
+        )?;
+    }
+    if owed_b > 0 {
+        token::transfer(
+            CpiContext::new_with_signer(
+                accts.token_program.to_account_info(),
+                Transfer {
+                    from: accts.treasury_b.to_account_info(),
+                    to: accts.user_ata_b.to_account_info(),
+                    authority: pool_ai.clone(),
+                },
+                signer,
+            ),
+            owed_b,
+        )?;
+    }
+    Ok(())
+}
 fn mark_inactive_by_floor(pool: &mut Pool) {
     for b in pool.bands.iter_mut() {
         b.is_active = !(b.reserves_a < pool.inactive_floor_a && b.reserves_b < pool.inactive_floor_b);
     }
 }
+/// Grows or shrinks the band list at both edges so surviving bands keep their offset from
+/// the center index, and with it their price range once `recompute_bands` runs.
+/// Growing splits each old edge band's pool-owned liquidity evenly between it and the new
+/// bands beyond it (see `split_edge_pol`); LP shares stay put, as a position holds shares in
+/// a single band. Shrinking refuses to drop bands holding LP shares and merges their
+/// pool-owned and locked reserves into the nearest surviving band on the same side that has
+/// shares (the edge band if none has), failing rather than leave reserves without shares.
+fn resize_bands(pool: &mut Pool, n_new: usize) -> Result<()> {
+    let n_old = pool.n_bands as usize;
+    require!(n_old > 0 && pool.bands.len() == n_old, DlmmError::InvariantViolated);
+    let mid_old = (n_old - 1) / 2;
+    let mid_new = (n_new - 1) / 2;
+
+    if n_new >= n_old {
+        let lo = mid_new - mid_old;
+        let hi = n_new - n_old - lo;
+        let mut next_id = pool.next_band_id;
+        let mut fresh = |k: usize| -> Result<Vec<Band>> {
+            let mut v = Vec::with_capacity(k);
+            for _ in 0..k {
+                v.push(Band { id: next_id, is_active: true, ..Band::default() });
+                next_id = next_id.checked_add(1).ok_or(DlmmError::MathOverflow)?;
+            }
+            Ok(v)
+        };
+        let mut out = fresh(lo)?;
+        out.extend(pool.bands.iter().cloned());
+        out.extend(fresh(hi)?);
+        split_edge_pol(&mut out, lo, lo, true)?;
+        split_edge_pol(&mut out, lo + n_old - 1, hi, false)?;
+        pool.bands = out;
+        pool.next_band_id = next_id;
+        return Ok(());
+    }
+
+    let lo = mid_old - mid_new;
+    let hi = n_old - n_new - lo;
+    let mut kept = pool.bands[lo..n_old - hi].to_vec();
+    for (i, b) in pool.bands.iter().enumerate() {
+        if i >= lo && i < n_old - hi { continue; }
+        require!(b.total_shares == b.pol_shares + b.locked_shares, DlmmError::BandHasShares);
+        let into = if i < lo {
+            kept.iter().position(|k| k.total_shares > 0).unwrap_or(0)
+        } else {
+            kept.iter().rposition(|k| k.total_shares > 0).unwrap_or(kept.len() - 1)
+        };
+        let minted = mint_pol(&mut kept[into], b.reserves_a, b.reserves_b)?;
+        require!(minted > 0 || (b.reserves_a == 0 && b.reserves_b == 0), DlmmError::InsufficientLiquidity);
+    }
+    pool.bands = kept;
+    Ok(())
+}
+
+/// Moves `k/(k+1)` of the pool-owned value of `bands[edge]` into the `k` empty bands just
+/// below (`below`) or above it, split evenly. The new bands get provisional price ranges
+/// continuing the edge band's spacing so they can be valued; `recompute_bands` sets the
+/// final ones. Anything a new band cannot mint goes back to the edge band.
+fn split_edge_pol(bands: &mut [Band], edge: usize, k: usize, below: bool) -> Result<()> {
+    let step = bands[edge].upper_price_1e6.saturating_sub(bands[edge].lower_price_1e6);
+    if k == 0 || step == 0 {
+        return Ok(());
+    }
+    let at = |j: usize| if below { edge - j } else { edge + j };
+    for j in 1..=k {
+        let (lower, upper) = if below {
+            let upper = bands[at(j - 1)].lower_price_1e6;
+            (upper.saturating_sub(step), upper)
+        } else {
+            let lower = bands[at(j - 1)].upper_price_1e6;
+            (lower, lower.saturating_add(step))
+        };
+        if lower == 0 || lower >= upper {
+            return Ok(());
+        }
+        bands[at(j)].lower_price_1e6 = lower;
+        bands[at(j)].upper_price_1e6 = upper;
+    }
+
+    let held = pol_value(&bands[edge])?;
+    if held == 0 {
+        return Ok(());
+    }
+    let (pot_a, pot_b) = burn_pol(&mut bands[edge], held * k as u128 / (k as u128 + 1), held);
+    let (mut left_a, mut left_b) = (pot_a, pot_b);
+    for j in 1..=k {
+        let (add_a, add_b) = if j == k { (left_a, left_b) } else { (pot_a / k as u64, pot_b / k as u64) };
+        if mint_pol(&mut bands[at(j)], add_a, add_b)? > 0 {
+            left_a -= add_a;
+            left_b -= add_b;
+        }
+    }
+    if left_a > 0 || left_b > 0 {
+        require!(mint_pol(&mut bands[edge], left_a, left_b)? > 0, DlmmError::InsufficientLiquidity);
+    }
+    Ok(())
+}
+
+/// Drops inactive bands nobody holds shares in. Survivors keep their ids, so positions and
+/// book levels still resolve to the same liquidity after indices shift.
 fn compact_active_bands(pool: &mut Pool) {
     let mut out = Vec::with_capacity(pool.bands.len());
     for b in pool.bands.iter() {
-        if b.is_active {
+        if b.is_active || b.total_shares > 0 {
             out.push(b.clone());
         }
     }
@@ -1622,6 +3834,15 @@ fn renormalize_active_weights(pool: &mut Pool) -> Result<()> {
     pool.total_weight_bps = pool.bands.iter().map(|b| b.weight_bps as u32).sum::<u32>();
     Ok(())
 }
+/// Fee charged on a gross input amount, rounded up.
+fn fee_on_gross(gross: u64, fee_bps: u16) -> u64 {
+    ((u128::from(gross) * u128::from(fee_bps)).div_ceil(10_000u128)) as u64
+}
+/// Smallest gross input whose net-of-fee amount covers `net`.
+fn gross_of_net(net: u64, fee_bps: u16) -> u64 {
+    let den = 10_000u128 - u128::from(fee_bps.min(9_999));
+    (u128::from(net) * 10_000u128).div_ceil(den).min(u64::MAX as u128) as u64
+}
 fn mul_div_1e18(a: u64, growth: u128) -> u64 {
     let num = (u128::from(a)).saturating_mul(growth);
     (num / 1_000_000_000_000_000_000u128) as u64
@@ -1659,37 +3880,32 @@ fn pay_bounty_if_any<'info>(
         return Ok(());
     }
 
-    // create seeds from the mutable pool state (pool_mut)
-    let seeds = pool_signer_seeds(&*pool_mut);
-    let signer = &[&seeds[..]];
-
-    if treasury_a.amount >= amount && dst_a.mint == treasury_a.mint {
-        token::transfer(
-            CpiContext::new_with_signer(
-                token_program.to_account_info(),
-                Transfer {
-                    from: treasury_a.to_account_info(),
-                    to: dst_a.to_account_info(),
-                    authority: pool_mut.to_account_info(),
-                },
-                signer,
-            ),
-            amount,
-        )?;
-    } else if treasury_b.amount >= amount && dst_b.mint == treasury_b.mint {
-        token::transfer(
-            CpiContext::new_with_signer(
-                token_program.to_account_info(),
-                Transfer {
-                    from: treasury_b.to_account_info(),
-                    to: dst_b.to_account_info(),
-                    authority: pool_mut.to_account_info(),
-                },
-                signer,
-            ),
-            amount,
-        )?;
-    }
+    // only the funded budget pays: the rest of the treasuries is owed to LPs and the protocol
+    let (from, to) = if pool_mut.bounty_budget_a >= amount && treasury_a.amount >= amount && dst_a.mint == treasury_a.mint {
+        pool_mut.bounty_budget_a -= amount;
+        (treasury_a, dst_a)
+    } else if pool_mut.bounty_budget_b >= amount && treasury_b.amount >= amount && dst_b.mint == treasury_b.mint {
+        pool_mut.bounty_budget_b -= amount;
+        (treasury_b, dst_b)
+    } else {
+        return Ok(());
+    };
+
+    // create seeds from the mutable pool state (pool_mut)
+    let seeds = pool_signer_seeds(&*pool_mut);
+    let signer = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: pool_mut.to_account_info(),
+            },
+            signer,
+        ),
+        amount,
+    )?;
     Ok(())
 }
 
@@ -1849,10 +4065,13 @@ pub struct ViewPool<'info> {
         bump = pool.bump
     )]
     pub pool: Account<'info, Pool>,
+    /// CHECK: the pool's orderbook PDA; `quote_swap` decodes it itself, into its scratch copy
+    #[account(owner = crate::ID, seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()], bump)]
+    pub orderbook: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-#[instruction(band_idx: u8, amount_a: u64, amount_b: u64, receipt_nonce: u64, min_unlock_after_slots: u64)]
+#[instruction(band_idx: u8, amount_a: u64, amount_b: u64, receipt_nonce: u64, min_unlock_after_slots: u64, min_shares_out: u64, expiry_slot: u64)]
 pub struct AddLiquidity<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -1925,6 +4144,40 @@ pub struct RemoveLiquidity<'info> {
     pub mint_b: Account<'info, Mint>,
 }
 
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = user_ata_a.mint == pool.mint_a)]
+    pub user_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_b.mint == pool.mint_b)]
+    pub user_ata_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    #[account(address = pool.mint_a)]
+    pub mint_a: Account<'info, Mint>,
+    #[account(address = pool.mint_b)]
+    pub mint_b: Account<'info, Mint>,
+}
+
 #[derive(Accounts)]
 pub struct WithPosition<'info> {
     #[account(mut)]
@@ -1954,6 +4207,81 @@ pub struct WithPosition<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+#[instruction(p: RangeDepositParams)]
+pub struct AddRangeLiquidity<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = user_ata_a.mint == pool.mint_a)]
+    pub user_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_b.mint == pool.mint_b)]
+    pub user_ata_b: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + RangePosition::space(p.upper_band.saturating_sub(p.lower_band) as usize + 1),
+        seeds = [b"v3".as_ref(), b"range".as_ref(), pool.key().as_ref(), user.key().as_ref(), p.receipt_nonce.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, RangePosition>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct WithRangePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"range".as_ref(), pool.key().as_ref(), position.owner.as_ref(), position.receipt_nonce.to_le_bytes().as_ref()],
+        bump = position.bump,
+        has_one = pool @ DlmmError::Unauthorized,
+        constraint = position.owner == user.key() @ DlmmError::Unauthorized
+    )]
+    pub position: Account<'info, RangePosition>,
+
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_a.mint == pool.mint_a)]
+    pub user_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_b.mint == pool.mint_b)]
+    pub user_ata_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+}
+
 #[derive(Accounts)]
 pub struct RiskScoped<'info> {
     #[account(mut)]
@@ -1998,6 +4326,77 @@ pub struct FeeScoped<'info> {
         constraint = pool.fee_admin == fee_admin.key() || is_admin(&pool.admins, &fee_admin.key())
     )]
     pub pool: Account<'info, Pool>,
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+    #[account(mut, constraint = dst_a.mint == pool.mint_a)]
+    pub dst_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = dst_b.mint == pool.mint_b)]
+    pub dst_b: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundBounty<'info> {
+    pub funder: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+    #[account(mut, constraint = funder_ata_a.mint == pool.mint_a)]
+    pub funder_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = funder_ata_b.mint == pool.mint_b)]
+    pub funder_ata_b: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PolScoped<'info> {
+    pub fee_admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump,
+        constraint = pool.fee_admin == fee_admin.key() || is_admin(&pool.admins, &fee_admin.key())
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RebalanceBands<'info> {
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+    #[account(address = pool.mint_a)]
+    pub mint_a: Account<'info, Mint>,
+    #[account(address = pool.mint_b)]
+    pub mint_b: Account<'info, Mint>,
 }
 
 #[derive(Accounts)]
@@ -2012,6 +4411,33 @@ pub struct AdminScoped<'info> {
     pub pool: Account<'info, Pool>,
 }
 
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    pub any_admin: Signer<'info>,
+    /// CHECK: still in a pre-v4 layout; `migrate_pool_versions` checks discriminator, seeds
+    /// and admin against the decoded legacy data
+    #[account(mut, owner = crate::ID)]
+    pub pool: UncheckedAccount<'info>,
+    /// CHECK: the pool's orderbook PDA, possibly never created; rebuilt when it holds a pre-v4 book
+    #[account(mut, seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()], bump)]
+    pub orderbook: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePosition<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: still in the pre-v4 layout; `migrate_position` checks discriminator and pool
+    #[account(mut, owner = crate::ID)]
+    pub position: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct AdminMultisig<'info> {
     #[account(
@@ -2045,31 +4471,117 @@ pub struct InitMetrics<'info> {
 /* --------- Orderbook account contexts --------- */
 
 #[derive(Accounts)]
-pub struct InitOrderBook<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
+pub struct InitOrderBook<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = payer,
+        space = ORDERBOOK_SPACE,
+        seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()],
+        bump
+    )]
+    pub orderbook: Account<'info, OrderBook>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()],
+        bump = orderbook.bump,
+        has_one = pool @ DlmmError::Unauthorized
+    )]
+    pub orderbook: Account<'info, OrderBook>,
+
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, address = pool.treasury_a)]
+    pub treasury_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.treasury_b)]
+    pub treasury_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = user_ata_a.mint == pool.mint_a)]
+    pub user_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_b.mint == pool.mint_b)]
+    pub user_ata_b: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + OpenOrders::SIZE,
+        seeds = [b"v3".as_ref(), b"open_orders".as_ref(), pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()],
+        bump = orderbook.bump,
+        has_one = pool @ DlmmError::Unauthorized
+    )]
+    pub orderbook: Account<'info, OrderBook>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"open_orders".as_ref(), pool.key().as_ref(), user.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+}
+
+#[derive(Accounts)]
+pub struct ConsumeEvents<'info> {
     #[account(
         seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
         bump = pool.bump
     )]
     pub pool: Account<'info, Pool>,
     #[account(
-        init,
-        payer = payer,
-        space = ORDERBOOK_SPACE,
+        mut,
         seeds = [b"v3".as_ref(), b"orderbook".as_ref(), pool.key().as_ref()],
-        bump
+        bump = orderbook.bump,
+        has_one = pool @ DlmmError::Unauthorized
     )]
     pub orderbook: Account<'info, OrderBook>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct PlaceOrder<'info> {
-    #[account(mut)]
+pub struct SettleFunds<'info> {
     pub user: Signer<'info>,
     #[account(
-        mut,
         seeds = [b"v3".as_ref(), b"pool".as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
         bump = pool.bump
     )]
@@ -2081,6 +4593,24 @@ pub struct PlaceOrder<'info> {
         has_one = pool @ DlmmError::Unauthorized
     )]
     pub orderbook: Account<'info, OrderBook>,
+    #[account(
+        mut,
+        seeds = [b"v3".as_ref(), b"open_orders".as_ref(), pool.key().as_ref(), user.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+
+    #[account(mut, address = pool.vault_a)]
+    pub vault_a: Account<'info, TokenAccount>,
+    #[account(mut, address = pool.vault_b)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = user_ata_a.mint == pool.mint_a)]
+    pub user_ata_a: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_ata_b.mint == pool.mint_b)]
+    pub user_ata_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -2232,6 +4762,7 @@ pub struct Pool {
     // routing / stp / book cache
     pub stp_mode: u8,
     pub route_mode: u8,
+    /// best resting bid / ask price, refreshed from the book's levels after every book change
     pub best_bid_1e6: u64,
     pub best_ask_1e6: u64,
     pub book_depth_bps: u16,
@@ -2240,6 +4771,20 @@ pub struct Pool {
     pub bands: Vec<Band>,
 
     pub _reserved: [u8; 128],
+
+    // v4 fields; appended so the v3 prefix keeps its offsets
+
+    // protocol fee: cut of each swap fee, accrued apart from LP fee growth
+    pub protocol_fee_bps: u16,
+    pub protocol_fees_a: u64,
+    pub protocol_fees_b: u64,
+
+    // next `Band.id` to hand out; ids are never reused
+    pub next_band_id: u32,
+
+    // keeper bounty budget held in the treasuries; bounties are paid from it alone
+    pub bounty_budget_a: u64,
+    pub bounty_budget_b: u64,
 }
 
 
@@ -2256,6 +4801,12 @@ pub struct Band {
     pub util_a: u64,
     pub util_b: u64,
     pub is_active: bool,
+    /// part of `total_shares` owned by the pool itself; the only liquidity `rebalance_bands` moves
+    pub pol_shares: u64,
+    /// part of `total_shares` locked on an LP-funded first deposit (see `MIN_LIQUIDITY_SHARES`)
+    pub locked_shares: u64,
+    /// persistent identity; positions and book levels refer to bands by id, never by index
+    pub id: u32,
 }
 
 #[account]
@@ -2263,7 +4814,7 @@ pub struct Position {
     pub bump: u8,
     pub pool: Pubkey,
     pub owner: Pubkey,
-    pub band_idx: u8,
+    pub band_id: u32,
     pub shares: u64,
     pub last_fee_growth_a_1e18: u128,
     pub last_fee_growth_b_1e18: u128,
@@ -2273,7 +4824,34 @@ pub struct Position {
 }
 impl Position {
     // size estimate (adjust if you change fields)
-    pub const SIZE: usize = 1 + 32 + 32 + 1 + 8 + 16 + 16 + 8 + 8 + 1 + 32;
+    pub const SIZE: usize = 1 + 32 + 32 + 4 + 8 + 16 + 16 + 8 + 8 + 1 + 32;
+}
+
+/// Position spanning several bands, one leg per band it was opened over.
+#[account]
+pub struct RangePosition {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub receipt_nonce: u64,
+    pub min_unlock_slot: u64,
+    pub legs: Vec<RangeLeg>,
+}
+impl RangePosition {
+    pub fn space(n_legs: usize) -> usize {
+        1 + 32 + 32 + 8 + 8 + 4 + n_legs * RangeLeg::SIZE
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct RangeLeg {
+    pub band_id: u32,
+    pub shares: u64,
+    pub last_fee_growth_a_1e18: u128,
+    pub last_fee_growth_b_1e18: u128,
+}
+impl RangeLeg {
+    pub const SIZE: usize = 4 + 8 + 16 + 16;
 }
 
 /* --------------------------- Metrics & BandBook & OrderBook --------------- */
@@ -2338,41 +4916,551 @@ pub struct OrderBook {
     pub bump: u8,
     pub pool: Pubkey,
     pub tick_1e6: u64,
-    pub best_bid_band: i16,
-    pub best_ask_band: i16,
     pub next_order_id: u64,
+    /// non-empty tick levels, best first: bids by descending price, asks by ascending
     pub bids: Vec<PriceLevel>,
     pub asks: Vec<PriceLevel>,
+    /// oldest unconsumed event; `event_q_tail` is the next free slot
     pub event_q_head: u16,
     pub event_q: Vec<BookEvent>,
+    /// cap on the number of tick levels per side
     pub max_levels: u16,
     pub max_queue_per_level: u16,
+    /// resting orders; levels link into this slab by index
+    pub orders: Vec<Order>,
+    /// first free slab slot, chained through `Order.next`
+    pub free_head: u32,
+    /// token A / B held in the pool vaults for traders: the sum of all OpenOrders balances
+    pub escrow_a: u64,
+    pub escrow_b: u64,
+    pub event_q_tail: u16,
+    /// smallest `qty` (base units) an order may be placed or amended down to
+    pub min_order_qty: u64,
 }
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
 pub struct PriceLevel {
-    pub band_idx: i16,
+    /// tick-aligned price shared by every order at this level
+    pub price_1e6: u64,
     pub total_qty: u64,
+    /// oldest and newest order in `OrderBook.orders`, `ORDER_NIL` when empty
     pub head: u32,
     pub tail: u32,
 }
+impl Default for PriceLevel {
+    fn default() -> Self {
+        PriceLevel { price_1e6: 0, total_qty: 0, head: ORDER_NIL, tail: ORDER_NIL }
+    }
+}
+/// Per-user orderbook balances for one pool. `*_locked` is escrow behind resting orders,
+/// `*_free` is owed to the owner (fills, rebates, cancels) and paid out by `settle_funds`.
+/// Both live in the pool vaults and are included in `OrderBook.escrow_*`.
+#[account]
+pub struct OpenOrders {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub base_free: u64,
+    pub base_locked: u64,
+    pub quote_free: u64,
+    pub quote_locked: u64,
+}
+impl OpenOrders {
+    pub const SIZE: usize = 1 + 32 + 32 + 8 * 4;
+}
+/// A resting order. Slots with `order_id == 0` are free.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Order {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: Side,
+    pub price_1e6: u64,
+    /// unfilled base (token A) quantity
+    pub qty: u64,
+    /// input token still escrowed for `qty`: B for bids, A for asks
+    pub escrow: u64,
+    pub client_id: u64,
+    pub expiry_slot: u64,
+    pub reduce_only: bool,
+    pub prev: u32,
+    pub next: u32,
+}
+impl Order {
+    fn empty() -> Self {
+        Order {
+            order_id: 0,
+            owner: Pubkey::default(),
+            side: Side::Bid,
+            price_1e6: 0,
+            qty: 0,
+            escrow: 0,
+            client_id: 0,
+            expiry_slot: 0,
+            reduce_only: false,
+            prev: ORDER_NIL,
+            next: ORDER_NIL,
+        }
+    }
+}
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub enum BookEvent {
-    Fill { order_id: u64, qty: u64, price_1e6: u64, side: Side },
-    Out { order_id: u64, reason: u8 },
-    Place {
+    /// A maker order filled. `quote` is the token B leg at the maker's price, `released`
+    /// the maker's escrow it consumed and `rebate_b` extra B credited to the maker (fee
+    /// rebate, or unused bid escrow).
+    Fill {
         order_id: u64,
+        maker: Pubkey,
         side: Side,
-        band_idx: i16,
-        owner: Pubkey,
         qty: u64,
-        client_id: u64,
-        tif_expiry: u64,
-        reduce_only: bool,
+        price_1e6: u64,
+        quote: u64,
+        released: u64,
+        rebate_b: u64,
     },
+    /// Escrow released from an order without its owner present (self-trade prevention,
+    /// expiry); the order may still rest with less. `released` becomes free balance.
+    Out { order_id: u64, owner: Pubkey, side: Side, reason: u8, released: u64 },
 }
 impl Default for BookEvent {
     fn default() -> Self {
-        BookEvent::Out { order_id: 0, reason: 0 }
+        BookEvent::Out { order_id: 0, owner: Pubkey::default(), side: Side::Bid, reason: 0, released: 0 }
+    }
+}
+
+/* ------------------------------ Legacy layouts (v1-v3) ------------------ */
+
+// Pools before v4 stored `Band`s without `pol_shares`/`locked_shares`/`id`, governance params
+// without `protocol_fee_bps`, and no protocol fee or band id counters; positions pointed at
+// their band by index; orderbooks kept band-indexed levels and no order slab or escrow.
+// `migrate_pool_versions` / `migrate_position` decode the old bytes through these mirrors
+// and rewrite the account in the current layout.
+
+#[derive(AnchorDeserialize)]
+struct LegacyPool {
+    version: u8,
+    bump: u8,
+
+    // multisig
+    admin_threshold: u8,
+    admins: [Pubkey; MAX_ADMINS],
+
+    // scoped roles
+    risk_admin: Pubkey,
+    ops_admin: Pubkey,
+    fee_admin: Pubkey,
+
+    // assets
+    mint_a: Pubkey,
+    mint_b: Pubkey,
+    vault_a: Pubkey,
+    vault_b: Pubkey,
+    treasury_a: Pubkey,
+    treasury_b: Pubkey,
+
+    // updater/oracle
+    updater: Pubkey,
+    oracle_signer: Option<Pubkey>,
+
+    // band params
+    base_width_bps: u16,
+    min_width_bps: u16,
+    max_width_bps: u16,
+    width_slope_per_kbps: u16,
+    bias_per_kbps: u16,
+    decay_per_band_bps: u16,
+    n_bands: u8,
+
+    // EMA/TWAP/vol
+    y_a_bps: u16,
+    y_b_bps: u16,
+    spot_price_1e6: u64,
+    ema_y_a_bps: u16,
+    ema_y_b_bps: u16,
+    ema_spot_1e6: u64,
+    alpha_y_bps: u16,
+    alpha_spot_bps: u16,
+    alpha_twap_bps: u16,
+    alpha_vol_bps: u16,
+    twap_center_1e6: u64,
+    max_twap_dev_bps: u16,
+    vol_ema_bps: u16,
+
+    // dynamic fee
+    fee_base_bps: u16,
+    fee_k_per_bps: u16,
+    fee_max_bps: u16,
+    fee_current_bps: u16,
+    maker_rebate_max_bps: u16,
+    taker_min_bps: u16,
+
+    // CBs & cooldown
+    max_center_move_bps: u16,
+    max_width_change_bps: u16,
+    max_weight_shift_bps: u16,
+    min_update_interval_slots: u32,
+    last_update_slot: u64,
+
+    // hysteresis
+    hyst_center_bps: u16,
+    hyst_width_bps: u16,
+    hyst_required_n: u8,
+    hyst_ctr_center: u8,
+    hyst_ctr_width: u8,
+
+    // deposit ratio guard
+    deposit_ratio_min_bps: u16,
+    deposit_ratio_max_bps: u16,
+
+    // inactive floors
+    inactive_floor_a: u64,
+    inactive_floor_b: u64,
+
+    // bounty/incentives
+    bounty_rate_microunits: u64,
+    bounty_max: u64,
+    stale_slots_for_boost: u64,
+    bounty_boost_bps: u16,
+    needs_update: bool,
+    min_cu_price: u64,
+
+    // diagnostics
+    last_width_bps: u16,
+    last_center_price_1e6: u64,
+    total_weight_bps: u32,
+
+    // flags
+    is_paused: bool,
+    pause_bands: bool,
+    pause_deposits: bool,
+    pause_withdraws: bool,
+    pause_orderbook: bool,
+    post_only_until_slot: u64,
+
+    // governance pending (timelock)
+    g_pending: Option<LegacyGovProposal>,
+
+    // mint rotation proposals
+    proposed_mint_a: Option<Pubkey>,
+    proposed_mint_b: Option<Pubkey>,
+
+    // routing / stp / book cache
+    stp_mode: u8,
+    route_mode: u8,
+    best_bid_1e6: u64,
+    best_ask_1e6: u64,
+    book_depth_bps: u16,
+
+    // bands
+    bands: Vec<LegacyBand>,
+
+    _reserved: [u8; 128],
+}
+impl LegacyPool {
+    fn into_current(self) -> Pool {
+        let bands: Vec<Band> = self
+            .bands
+            .into_iter()
+            .enumerate()
+            .map(|(i, b)| Band {
+                lower_price_1e6: b.lower_price_1e6,
+                upper_price_1e6: b.upper_price_1e6,
+                weight_bps: b.weight_bps,
+                fee_growth_a_1e18: b.fee_growth_a_1e18,
+                fee_growth_b_1e18: b.fee_growth_b_1e18,
+                reserves_a: b.reserves_a,
+                reserves_b: b.reserves_b,
+                total_shares: b.total_shares,
+                util_a: b.util_a,
+                util_b: b.util_b,
+                is_active: b.is_active,
+                pol_shares: 0,
+                locked_shares: 0,
+                id: i as u32,
+            })
+            .collect();
+        let next_band_id = bands.len() as u32;
+        Pool {
+            version: POOL_VERSION,
+            bump: self.bump,
+            admin_threshold: self.admin_threshold,
+            admins: self.admins,
+            risk_admin: self.risk_admin,
+            ops_admin: self.ops_admin,
+            fee_admin: self.fee_admin,
+            mint_a: self.mint_a,
+            mint_b: self.mint_b,
+            vault_a: self.vault_a,
+            vault_b: self.vault_b,
+            treasury_a: self.treasury_a,
+            treasury_b: self.treasury_b,
+            updater: self.updater,
+            oracle_signer: self.oracle_signer,
+            base_width_bps: self.base_width_bps,
+            min_width_bps: self.min_width_bps,
+            max_width_bps: self.max_width_bps,
+            width_slope_per_kbps: self.width_slope_per_kbps,
+            bias_per_kbps: self.bias_per_kbps,
+            decay_per_band_bps: self.decay_per_band_bps,
+            n_bands: self.n_bands,
+            y_a_bps: self.y_a_bps,
+            y_b_bps: self.y_b_bps,
+            spot_price_1e6: self.spot_price_1e6,
+            ema_y_a_bps: self.ema_y_a_bps,
+            ema_y_b_bps: self.ema_y_b_bps,
+            ema_spot_1e6: self.ema_spot_1e6,
+            alpha_y_bps: self.alpha_y_bps,
+            alpha_spot_bps: self.alpha_spot_bps,
+            alpha_twap_bps: self.alpha_twap_bps,
+            alpha_vol_bps: self.alpha_vol_bps,
+            twap_center_1e6: self.twap_center_1e6,
+            max_twap_dev_bps: self.max_twap_dev_bps,
+            vol_ema_bps: self.vol_ema_bps,
+            fee_base_bps: self.fee_base_bps,
+            fee_k_per_bps: self.fee_k_per_bps,
+            fee_max_bps: self.fee_max_bps,
+            fee_current_bps: self.fee_current_bps,
+            maker_rebate_max_bps: self.maker_rebate_max_bps,
+            taker_min_bps: self.taker_min_bps,
+            max_center_move_bps: self.max_center_move_bps,
+            max_width_change_bps: self.max_width_change_bps,
+            max_weight_shift_bps: self.max_weight_shift_bps,
+            min_update_interval_slots: self.min_update_interval_slots,
+            last_update_slot: self.last_update_slot,
+            hyst_center_bps: self.hyst_center_bps,
+            hyst_width_bps: self.hyst_width_bps,
+            hyst_required_n: self.hyst_required_n,
+            hyst_ctr_center: self.hyst_ctr_center,
+            hyst_ctr_width: self.hyst_ctr_width,
+            deposit_ratio_min_bps: self.deposit_ratio_min_bps,
+            deposit_ratio_max_bps: self.deposit_ratio_max_bps,
+            inactive_floor_a: self.inactive_floor_a,
+            inactive_floor_b: self.inactive_floor_b,
+            bounty_rate_microunits: self.bounty_rate_microunits,
+            bounty_max: self.bounty_max,
+            stale_slots_for_boost: self.stale_slots_for_boost,
+            bounty_boost_bps: self.bounty_boost_bps,
+            needs_update: self.needs_update,
+            min_cu_price: self.min_cu_price,
+            last_width_bps: self.last_width_bps,
+            last_center_price_1e6: self.last_center_price_1e6,
+            total_weight_bps: self.total_weight_bps,
+            is_paused: self.is_paused,
+            pause_bands: self.pause_bands,
+            pause_deposits: self.pause_deposits,
+            pause_withdraws: self.pause_withdraws,
+            pause_orderbook: self.pause_orderbook,
+            post_only_until_slot: self.post_only_until_slot,
+            g_pending: self.g_pending.map(LegacyGovProposal::into_current),
+            proposed_mint_a: self.proposed_mint_a,
+            proposed_mint_b: self.proposed_mint_b,
+            stp_mode: self.stp_mode,
+            route_mode: self.route_mode,
+            best_bid_1e6: self.best_bid_1e6,
+            best_ask_1e6: self.best_ask_1e6,
+            book_depth_bps: self.book_depth_bps,
+            bands,
+            _reserved: self._reserved,
+            protocol_fee_bps: 0,
+            protocol_fees_a: 0,
+            protocol_fees_b: 0,
+            next_band_id,
+            bounty_budget_a: 0,
+            bounty_budget_b: 0,
+        }
+    }
+}
+
+#[derive(AnchorDeserialize)]
+struct LegacyBand {
+    lower_price_1e6: u64,
+    upper_price_1e6: u64,
+    weight_bps: u16,
+    fee_growth_a_1e18: u128,
+    fee_growth_b_1e18: u128,
+    reserves_a: u64,
+    reserves_b: u64,
+    total_shares: u64,
+    util_a: u64,
+    util_b: u64,
+    is_active: bool,
+}
+
+#[derive(AnchorDeserialize)]
+struct LegacyGovProposal {
+    new: LegacySettableParams,
+    queued_at: u64,
+    earliest_exec: u64,
+    deadline: u64,
+    executed: bool,
+}
+impl LegacyGovProposal {
+    fn into_current(self) -> GovProposal {
+        GovProposal {
+            new: self.new.into_current(),
+            queued_at: self.queued_at,
+            earliest_exec: self.earliest_exec,
+            deadline: self.deadline,
+            executed: self.executed,
+        }
+    }
+}
+
+#[derive(AnchorDeserialize)]
+struct LegacyPosition {
+    bump: u8,
+    pool: Pubkey,
+    owner: Pubkey,
+    band_idx: u8,
+    shares: u64,
+    last_fee_growth_a_1e18: u128,
+    last_fee_growth_b_1e18: u128,
+    receipt_nonce: u64,
+    min_unlock_slot: u64,
+    approved: Option<Pubkey>,
+}
+impl LegacyPosition {
+    fn into_current(self) -> Position {
+        Position {
+            bump: self.bump,
+            pool: self.pool,
+            owner: self.owner,
+            band_id: self.band_idx as u32,
+            shares: self.shares,
+            last_fee_growth_a_1e18: self.last_fee_growth_a_1e18,
+            last_fee_growth_b_1e18: self.last_fee_growth_b_1e18,
+            receipt_nonce: self.receipt_nonce,
+            min_unlock_slot: self.min_unlock_slot,
+            approved: self.approved,
+        }
+    }
+}
+
+// Only the header and limits survive migration; levels and events are decoded to reach them.
+#[derive(AnchorDeserialize)]
+struct LegacyOrderBook {
+    bump: u8,
+    pool: Pubkey,
+    tick_1e6: u64,
+    _best_bid_band: i16,
+    _best_ask_band: i16,
+    next_order_id: u64,
+    _bids: Vec<LegacyPriceLevel>,
+    _asks: Vec<LegacyPriceLevel>,
+    _event_q_head: u16,
+    _event_q: Vec<LegacyBookEvent>,
+    max_levels: u16,
+    max_queue_per_level: u16,
+}
+
+#[derive(AnchorDeserialize)]
+struct LegacyPriceLevel {
+    _band_idx: i16,
+    _total_qty: u64,
+    _head: u32,
+    _tail: u32,
+}
+
+#[derive(AnchorDeserialize)]
+enum LegacyBookEvent {
+    Fill { _order_id: u64, _qty: u64, _price_1e6: u64, _side: Side },
+    Out { _order_id: u64, _reason: u8 },
+    Place {
+        _order_id: u64,
+        _side: Side,
+        _band_idx: i16,
+        _owner: Pubkey,
+        _qty: u64,
+        _client_id: u64,
+        _tif_expiry: u64,
+        _reduce_only: bool,
+    },
+}
+
+#[derive(AnchorDeserialize)]
+struct LegacySettableParams {
+    n_bands: Option<u8>,
+    base_width_bps: Option<u16>,
+    min_width_bps: Option<u16>,
+    max_width_bps: Option<u16>,
+    width_slope_per_kbps: Option<u16>,
+    bias_per_kbps: Option<u16>,
+    decay_per_band_bps: Option<u16>,
+
+    alpha_y_bps: Option<u16>,
+    alpha_spot_bps: Option<u16>,
+    alpha_twap_bps: Option<u16>,
+    alpha_vol_bps: Option<u16>,
+    max_twap_dev_bps: Option<u16>,
+
+    fee_base_bps: Option<u16>,
+    fee_k_per_bps: Option<u16>,
+    fee_max_bps: Option<u16>,
+
+    hyst_center_bps: Option<u16>,
+    hyst_width_bps: Option<u16>,
+    hyst_required_n: Option<u8>,
+
+    deposit_ratio_min_bps: Option<u16>,
+    deposit_ratio_max_bps: Option<u16>,
+
+    inactive_floor_a: Option<u64>,
+    inactive_floor_b: Option<u64>,
+
+    bounty_rate_microunits: Option<u64>,
+    bounty_max: Option<u64>,
+    stale_slots_for_boost: Option<u64>,
+    bounty_boost_bps: Option<u16>,
+    min_cu_price: Option<u64>,
+
+    max_center_move_bps: Option<u16>,
+    max_width_change_bps: Option<u16>,
+    max_weight_shift_bps: Option<u16>,
+    min_update_interval_slots: Option<u32>,
+
+    maker_rebate_max_bps: Option<u16>,
+    taker_min_bps: Option<u16>,
+    stp_mode: Option<StpMode>,
+    route_mode: Option<RouteMode>,
+}
+impl LegacySettableParams {
+    fn into_current(self) -> SettableParamsV3 {
+        SettableParamsV3 {
+            n_bands: self.n_bands,
+            base_width_bps: self.base_width_bps,
+            min_width_bps: self.min_width_bps,
+            max_width_bps: self.max_width_bps,
+            width_slope_per_kbps: self.width_slope_per_kbps,
+            bias_per_kbps: self.bias_per_kbps,
+            decay_per_band_bps: self.decay_per_band_bps,
+            alpha_y_bps: self.alpha_y_bps,
+            alpha_spot_bps: self.alpha_spot_bps,
+            alpha_twap_bps: self.alpha_twap_bps,
+            alpha_vol_bps: self.alpha_vol_bps,
+            max_twap_dev_bps: self.max_twap_dev_bps,
+            fee_base_bps: self.fee_base_bps,
+            fee_k_per_bps: self.fee_k_per_bps,
+            fee_max_bps: self.fee_max_bps,
+            hyst_center_bps: self.hyst_center_bps,
+            hyst_width_bps: self.hyst_width_bps,
+            hyst_required_n: self.hyst_required_n,
+            deposit_ratio_min_bps: self.deposit_ratio_min_bps,
+            deposit_ratio_max_bps: self.deposit_ratio_max_bps,
+            inactive_floor_a: self.inactive_floor_a,
+            inactive_floor_b: self.inactive_floor_b,
+            bounty_rate_microunits: self.bounty_rate_microunits,
+            bounty_max: self.bounty_max,
+            stale_slots_for_boost: self.stale_slots_for_boost,
+            bounty_boost_bps: self.bounty_boost_bps,
+            min_cu_price: self.min_cu_price,
+            max_center_move_bps: self.max_center_move_bps,
+            max_width_change_bps: self.max_width_change_bps,
+            max_weight_shift_bps: self.max_weight_shift_bps,
+            min_update_interval_slots: self.min_update_interval_slots,
+            maker_rebate_max_bps: self.maker_rebate_max_bps,
+            taker_min_bps: self.taker_min_bps,
+            stp_mode: self.stp_mode,
+            route_mode: self.route_mode,
+            protocol_fee_bps: None,
+        }
     }
 }
 
@@ -2483,6 +5571,7 @@ pub struct SettableParamsV3 {
     pub taker_min_bps: Option<u16>,
     pub stp_mode: Option<StpMode>,
     pub route_mode: Option<RouteMode>,
+    pub protocol_fee_bps: Option<u16>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
@@ -2494,15 +5583,38 @@ pub struct PauseFlags {
     pub pause_orderbook: Option<bool>,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RangeDepositParams {
+    pub lower_band: u8,
+    pub upper_band: u8,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub mode: DistributionMode,
+    /// one entry per band in the range; only read for `DistributionMode::Custom`
+    pub weights_bps: Vec<u16>,
+    pub receipt_nonce: u64,
+    pub min_unlock_after_slots: u64,
+    pub min_shares_out: u64,
+    pub expiry_slot: u64,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
 pub enum Side { Bid, Ask }
 impl Side {
     pub fn opposite(self) -> Side { match self { Side::Bid => Side::Ask, Side::Ask => Side::Bid } }
 }
+/// Which venue a taker order trades against: only the book, only the bands, or both
+/// interleaved in price order (`BestPrice`).
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
-pub enum RouteMode { BookFirst, DlmmFirst }
+pub enum RouteMode { BookFirst, DlmmFirst, BestPrice }
 impl RouteMode {
-    pub fn from_u8(v: u8) -> RouteMode { if v == 0 { RouteMode::BookFirst } else { RouteMode::DlmmFirst } }
+    pub fn from_u8(v: u8) -> RouteMode {
+        match v {
+            0 => RouteMode::BookFirst,
+            2 => RouteMode::BestPrice,
+            _ => RouteMode::DlmmFirst,
+        }
+    }
 }
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
 pub enum StpMode { None, DecrementAndCancel, CancelNewest, CancelOldest }
@@ -2511,16 +5623,34 @@ impl StpMode {
         match v { 1 => StpMode::DecrementAndCancel, 2 => StpMode::CancelNewest, 3 => StpMode::CancelOldest, _ => StpMode::None }
     }
 }
+/// How a range deposit is split across its bands. Inactive bands get nothing under
+/// `Uniform` and `Weight`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DistributionMode { Uniform, Weight, Custom }
+/// `kind`: 0 IOC, 1 GTC, 2 GTT (until `gtt_expiry_slot`), 3 FOK (fill fully or revert).
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
 pub struct TifParam { pub kind: u8, pub gtt_expiry_slot: u64 }
 impl TifParam {
-    pub fn is_ioc(&self) -> bool { self.kind == 0 }
-    pub fn as_expiry(&self, now: u64) -> u64 { match self.kind { 0 => now, 1 => u64::MAX, _ => self.gtt_expiry_slot } }
+    pub fn is_ioc(&self) -> bool { self.kind == 0 || self.kind == 3 }
+    pub fn is_fok(&self) -> bool { self.kind == 3 }
+    pub fn as_expiry(&self, now: u64) -> u64 { match self.kind { 0 | 3 => now, 1 => u64::MAX, _ => self.gtt_expiry_slot } }
 }
+/// Post-only handling for `place_order`: `Reject` fails with `WouldCross` if the order
+/// would take, `Slide` reprices it to one tick behind the opposite best instead.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PostOnly { None, Reject, Slide }
+/// One entry of `place_orders_batch`: a limit order at `price_1e6`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct BatchOrder { pub side: Side, pub qty: u64, pub price_1e6: u64, pub tif: TifParam, pub client_id: u64 }
+/// Names a resting order either by its book-assigned id or by the owner's `client_id`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OrderRef { Id(u64), ClientId(u64) }
 
 /* ------------------------------ Events ------------------------------------ */
 
-pub const EVENT_VERSION: u8 = 3;
+/// Bumped whenever an event's layout changes. 4: `OrderPlacedV3` and `DepthItem` no longer
+/// carry `band_idx`, as book levels are keyed by price, and `SwapFilledV` names its taker.
+pub const EVENT_VERSION: u8 = 4;
 
 #[event]
 pub struct PoolInitializedV {
@@ -2541,6 +5671,24 @@ pub struct PoolMigratedV {
 }
 
 
+#[event]
+pub struct BandsRebalancedV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub moved_value_b: u64,
+    pub pol_value_b: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PoolLiquiditySeededV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub shares: u64,
+}
+
 #[event]
 pub struct BandsDigestUpdatedV {
     pub event_version: u8,
@@ -2604,7 +5752,17 @@ pub struct OrderPlacedV3 {
     pub side: Side,
     pub price_1e6: u64,
     pub qty: u64,
-    pub band_idx: i16,
+}
+
+#[event]
+pub struct OrderAmendedV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: Side,
+    pub price_1e6: u64,
+    pub qty: u64,
 }
 
 #[event]
@@ -2628,10 +5786,29 @@ pub struct OrderCanceledV3 {
     pub side: Side,
 }
 
+#[event]
+pub struct FundsSettledV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct EventsConsumedV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub consumed: u16,
+    pub remaining: u16,
+}
+
+/// One band fill of a swap or of an order's band leg; `OrderFilledV3` covers book fills.
 #[event]
 pub struct SwapFilledV {
     pub event_version: u8,
     pub pool: Pubkey,
+    pub taker: Pubkey,
     pub side: Side,
     pub qty: u64,
     pub price_1e6: u64,
@@ -2667,12 +5844,43 @@ pub struct EmergencyDrainV {
     pub amount: u64,
 }
 
+#[event]
+pub struct ProtocolFeesWithdrawnV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct BountyBudgetFundedV {
+    pub event_version: u8,
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+/// Return data of `quote_swap`. `amount_in` is in the input token and `amount_out` in the
+/// output token, both with every fee included.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct SwapQuote {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// band fees, in the input token
+    pub fee_paid: u64,
+    /// book taker fees, always in token B
+    pub book_fee_b: u64,
+    /// base filled against resting orders; the rest of the fill is from the bands
+    pub book_qty: u64,
+    pub bands_touched: u16,
+    pub effective_price_1e6: u64,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct DepthItem {
     pub price_1e6: u64,
     pub bid_qty: u64,
     pub ask_qty: u64,
-    pub band_idx: u16,
 }
 
 /* ------------------------------ Errors ------------------------------------ */
@@ -2741,6 +5949,38 @@ pub enum DlmmError {
     NotFound,
     #[msg("Bad multisig quorum")]
     BadQuorum,
+    #[msg("Slippage exceeded")]
+    SlippageExceeded,
+    #[msg("Expired")]
+    Expired,
+    #[msg("Insufficient liquidity")]
+    InsufficientLiquidity,
+    #[msg("Invalid distribution")]
+    InvalidDistribution,
+    #[msg("Band not found")]
+    BandNotFound,
+    #[msg("Band still holds LP shares")]
+    BandHasShares,
+    #[msg("Orderbook full")]
+    OrderbookFull,
+    #[msg("Event queue full")]
+    EventQueueFull,
+    #[msg("Fill-or-kill order not fully filled")]
+    FillOrKill,
+    #[msg("Post-only order would cross")]
+    WouldCross,
+    #[msg("Only post-only orders are accepted until the slot after a keeper update")]
+    PostOnlyWindow,
+    #[msg("Pool must be migrated to the current layout first")]
+    PoolNotMigrated,
+    #[msg("Liquidity amounts or shares outside the caller's bounds")]
+    LiquidityBoundExceeded,
+    #[msg("Order quantity below the book minimum")]
+    OrderTooSmall,
+    #[msg("Owner already has the maximum number of resting orders")]
+    TooManyOrders,
+    #[msg("Escrow or OpenOrders balance short of the amount leaving it")]
+    EscrowMismatch,
 }
 
 /* --------------------------- Small helpers -------------------------------- */
@@ -2764,7 +6004,13 @@ fn is_quorum(admins: &[Pubkey; MAX_ADMINS], threshold: u8, remaining: &[AccountI
 }
 
 fn apply_settable_params(pool: &mut Pool, s: &SettableParamsV3) -> Result<()> {
-    if let Some(v) = s.n_bands { require!(v as usize <= MAX_BANDS, DlmmError::InvalidNBands); pool.n_bands = v; }
+    if let Some(v) = s.n_bands {
+        require!(v > 0 && v as usize <= MAX_BANDS, DlmmError::InvalidNBands);
+        if !pool.bands.is_empty() && v != pool.n_bands {
+            resize_bands(pool, v as usize)?;
+        }
+        pool.n_bands = v;
+    }
     if let Some(v) = s.base_width_bps { pool.base_width_bps = v; }
     if let Some(v) = s.min_width_bps { pool.min_width_bps = v; }
     if let Some(v) = s.max_width_bps { pool.max_width_bps = v; }
@@ -2797,13 +6043,90 @@ fn apply_settable_params(pool: &mut Pool, s: &SettableParamsV3) -> Result<()> {
     if let Some(v) = s.min_update_interval_slots { pool.min_update_interval_slots = v; }
     if let Some(v) = s.maker_rebate_max_bps { pool.maker_rebate_max_bps = v; }
     if let Some(v) = s.taker_min_bps { pool.taker_min_bps = v; }
+    if let Some(v) = s.protocol_fee_bps { require!(v <= 10_000, DlmmError::ParamOutOfRange); pool.protocol_fee_bps = v; }
     if let Some(v) = s.stp_mode { pool.stp_mode = v as u8; }
     if let Some(v) = s.route_mode { pool.route_mode = v as u8; }
     Ok(())
 }
 
-fn quote_shares_to_mint(b: &Band, add_a: u64, add_b: u64) -> u64 {
-    add_a.max(add_b)
+/// Current band price: the curve's marginal price, or the range mid for an empty band.
+fn band_price_1e6(b: &Band) -> Result<u64> {
+    Ok(match BandCurve::from_band(b)? {
+        Some(c) => c.price_1e6(),
+        None => mid_price(b.lower_price_1e6, b.upper_price_1e6),
+    })
+}
+
+/// Value of (a, b) in token B units at `price_1e6`.
+fn value_in_b(a: u64, b: u64, price_1e6: u64) -> Result<u128> {
+    Ok(ck_mul(u128::from(a), u128::from(price_1e6))? / 1_000_000 + u128::from(b))
+}
+
+/// Shares for depositing (add_a, add_b) into `b`, both sides valued in B at the band's
+/// current price. The first deposit mints its value minus `MIN_LIQUIDITY_SHARES`, which
+/// stay locked in `total_shares` so the share price can't be inflated from a dust supply.
+/// Returns (shares to the depositor, shares locked).
+fn quote_shares_to_mint(b: &Band, add_a: u64, add_b: u64) -> Result<(u64, u64)> {
+    let px = band_price_1e6(b)?;
+    let dep = value_in_b(add_a, add_b, px)?;
+
+    if b.total_shares == 0 {
+        let dep = u64::try_from(dep).map_err(|_| DlmmError::MathOverflow)?;
+        require!(dep > MIN_LIQUIDITY_SHARES, DlmmError::ZeroShares);
+        return Ok((dep - MIN_LIQUIDITY_SHARES, MIN_LIQUIDITY_SHARES));
+    }
+
+    let held = value_in_b(b.reserves_a, b.reserves_b, px)?;
+    require!(held > 0, DlmmError::ZeroShares);
+    let shares = ck_mul(dep, u128::from(b.total_shares))? / held;
+    Ok((u64::try_from(shares).map_err(|_| DlmmError::MathOverflow)?, 0))
+}
+
+/// Value of the pool-owned part of a band, in B at the band's current price.
+fn pol_value(b: &Band) -> Result<u128> {
+    if b.pol_shares == 0 || b.total_shares == 0 {
+        return Ok(0);
+    }
+    let v = value_in_b(b.reserves_a, b.reserves_b, band_price_1e6(b)?)?;
+    Ok(ck_mul(v, u128::from(b.pol_shares))? / u128::from(b.total_shares))
+}
+
+/// Burns pool-owned shares worth `value` (out of `held`) and returns the reserves released,
+/// pro rata like `remove_liquidity`.
+fn burn_pol(b: &mut Band, value: u128, held: u128) -> (u64, u64) {
+    let burn = (u128::from(b.pol_shares) * value / held.max(1)) as u64;
+    if burn == 0 {
+        return (0, 0);
+    }
+    let out_a = (u128::from(b.reserves_a) * u128::from(burn) / u128::from(b.total_shares)) as u64;
+    let out_b = (u128::from(b.reserves_b) * u128::from(burn) / u128::from(b.total_shares)) as u64;
+    b.pol_shares -= burn;
+    b.total_shares -= burn;
+    b.reserves_a -= out_a;
+    b.reserves_b -= out_b;
+    (out_a, out_b)
+}
+
+/// Adds (add_a, add_b) to a band as pool-owned liquidity. On a band's first deposit the
+/// locked minimum is kept by the pool as well. Returns 0, leaving the band untouched, if
+/// the amounts are too small to mint.
+fn mint_pol(b: &mut Band, add_a: u64, add_b: u64) -> Result<u64> {
+    if add_a == 0 && add_b == 0 {
+        return Ok(0);
+    }
+    if b.total_shares == 0 && value_in_b(add_a, add_b, band_price_1e6(b)?)? <= u128::from(MIN_LIQUIDITY_SHARES) {
+        return Ok(0);
+    }
+    let (shares, locked) = quote_shares_to_mint(b, add_a, add_b)?;
+    let shares = shares + locked;
+    if shares == 0 {
+        return Ok(0);
+    }
+    b.total_shares = b.total_shares.checked_add(shares).ok_or(DlmmError::MathOverflow)?;
+    b.pol_shares += shares;
+    b.reserves_a = b.reserves_a.saturating_add(add_a);
+    b.reserves_b = b.reserves_b.saturating_add(add_b);
+    Ok(shares)
 }
 
 /* ================================ Constants ================================= */
@@ -2811,14 +6134,180 @@ fn quote_shares_to_mint(b: &Band, add_a: u64, add_b: u64) -> u64 {
 pub const MAX_ADMINS: usize = 8;
 pub const MAX_BANDS: usize = 64;
 pub const METRICS_CAP: usize = 128;
-pub const EVENT_Q_CAP: usize = 256;
+/// sized so a queue full of `Fill`s fits in `ORDERBOOK_SPACE` next to the order slab
+pub const EVENT_Q_CAP: usize = 64;
 pub const DEFAULT_MAX_QUEUE_PER_LEVEL: u16 = 64;
-
+/// resting orders per book: as many as fit in `ORDERBOOK_SPACE` with full levels and event
+/// queue. Deserializing grows the slab in the same 42- and 84-entry steps as a 64-order one,
+/// so it costs no extra heap.
+pub const MAX_ORDERS: usize = 84;
+pub const MAX_ORDERS_PER_OWNER: usize = 8;
+/// tick levels per side
+pub const MAX_LEVELS: usize = 64;
+/// `min_order_qty` given to books rebuilt by `migrate_pool_versions`
+pub const DEFAULT_MIN_ORDER_QTY: u64 = 1_000;
+pub const MAX_BATCH_ORDERS: usize = 20;
+pub const ORDER_NIL: u32 = u32::MAX;
+/// shares burned into each band on its first deposit (see `quote_shares_to_mint`)
+pub const MIN_LIQUIDITY_SHARES: u64 = 1_000;
+
+/// `Pool` layout version written by `initialize_pool` and `migrate_pool_versions`
+pub const POOL_VERSION: u8 = 4;
 pub const POOL_SPACE: usize = 16 * 1024;
 pub const ORDERBOOK_SPACE: usize = 16 * 1024;
 pub const METRICS_SPACE: usize = 8_000;
 
+/// fixed-point scale for sqrt prices in the band curve (sqrt of a 1e6 price times 1e12)
+pub const SQRT_Q: u128 = 1_000_000_000;
+
 pub const OUT_REASON_CANCEL: u8 = 1;
+pub const OUT_REASON_SELF_TRADE: u8 = 2;
+pub const OUT_REASON_EXPIRED: u8 = 3;
+
+/* =============================================================================
+                                   Tests
+============================================================================= */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(lower_price_1e6: u64, upper_price_1e6: u64, reserves_a: u64, reserves_b: u64) -> Band {
+        Band { lower_price_1e6, upper_price_1e6, reserves_a, reserves_b, is_active: true, ..Band::default() }
+    }
+
+    #[test]
+    fn band_curve_prices_reserves_near_u64_max() {
+        for b in [
+            band(997_500, 1_002_500, u64::MAX / 2, u64::MAX / 2),
+            band(900_000, 1_100_000, u64::MAX - 1, u64::MAX - 1),
+            band(900_000, 1_100_000, u64::MAX, 0),
+            band(900_000, 1_100_000, 0, u64::MAX),
+        ] {
+            let c = BandCurve::from_band(&b).unwrap().expect("band should be priced");
+            let px = c.price_1e6();
+            // sqrt rounding may put an edge price one unit outside the band
+            assert!(px + 1 >= b.lower_price_1e6 && px <= b.upper_price_1e6 + 1, "price {px} outside band");
+            assert_eq!(band_price_1e6(&b).unwrap(), px);
+        }
+    }
+
+    #[test]
+    fn band_curve_fills_large_narrow_band() {
+        // 1e16 per side in a 50 bps band used to overflow while solving L
+        let b = band(997_500, 1_002_500, 10_000_000_000_000_000, 10_000_000_000_000_000);
+        let c = BandCurve::from_band(&b).unwrap().expect("band should be priced");
+        let (used, paid) = c.fill(&b, Side::Bid, SwapMode::ExactIn, 1_000_000, u64::MAX).unwrap();
+        assert_eq!(used, 1_000_000);
+        assert!(paid > 0 && paid <= 1_002_600);
+        let (used, paid) = c.fill(&b, Side::Ask, SwapMode::ExactIn, 1_000_000, 0).unwrap();
+        assert_eq!(used, 1_000_000);
+        assert!(paid > 0 && paid <= 1_002_600);
+    }
+
+    #[test]
+    fn band_curve_small_reserves_unscaled() {
+        // below 2^36 the reserves are solved as is
+        let b = band(900_000, 1_100_000, 1_000_000, 1_000_000);
+        let c = BandCurve::from_band(&b).unwrap().expect("band should be priced");
+        let sa = sqrt_price_q(b.lower_price_1e6);
+        let sb = sqrt_price_q(b.upper_price_1e6);
+        assert_eq!(c.l, curve_liquidity(1_000_000, 1_000_000, sa, sb).unwrap());
+    }
+
+    fn full_book() -> OrderBook {
+        let fill = BookEvent::Fill {
+            order_id: u64::MAX,
+            maker: Pubkey::default(),
+            side: Side::Bid,
+            qty: 0,
+            price_1e6: 0,
+            quote: 0,
+            released: 0,
+            rebate_b: 0,
+        };
+        OrderBook {
+            bump: 0,
+            pool: Pubkey::default(),
+            tick_1e6: 1,
+            next_order_id: 1,
+            bids: vec![PriceLevel::default(); MAX_LEVELS],
+            asks: vec![PriceLevel::default(); MAX_LEVELS],
+            event_q_head: 0,
+            event_q: vec![fill; EVENT_Q_CAP],
+            max_levels: MAX_LEVELS as u16,
+            max_queue_per_level: DEFAULT_MAX_QUEUE_PER_LEVEL,
+            orders: vec![Order { order_id: 1, ..Order::empty() }; MAX_ORDERS],
+            free_head: ORDER_NIL,
+            escrow_a: 0,
+            escrow_b: 0,
+            event_q_tail: 0,
+            min_order_qty: DEFAULT_MIN_ORDER_QTY,
+        }
+    }
+
+    #[test]
+    fn full_orderbook_fits_its_account() {
+        let mut data = Vec::new();
+        full_book().try_serialize(&mut data).unwrap();
+        assert!(data.len() <= ORDERBOOK_SPACE, "{} > {}", data.len(), ORDERBOOK_SPACE);
+    }
+
+    #[test]
+    fn rest_in_book_caps_orders_per_owner() {
+        let owner = Pubkey::new_unique();
+        let mut ob = full_book();
+        ob.bids.clear();
+        ob.asks.clear();
+        ob.orders = vec![Order { order_id: 1, owner, ..Order::empty() }; MAX_ORDERS_PER_OWNER];
+        let gtc = TifParam { kind: 1, gtt_expiry_slot: 0 };
+        let err = rest_in_book(&mut ob, Side::Bid, 1_000_000, 1_000, 1_000, gtc, false, 0, owner).unwrap_err();
+        assert_eq!(err, error!(DlmmError::TooManyOrders));
+    }
+
+    #[test]
+    fn maker_credit_rejects_unlocking_more_than_locked() {
+        let mut oo = OpenOrders {
+            bump: 0,
+            pool: Pubkey::default(),
+            owner: Pubkey::default(),
+            base_free: 0,
+            base_locked: 100,
+            quote_free: 0,
+            quote_locked: 0,
+        };
+        let credit = MakerCredit { unlock_a: 101, free_a: 101, ..MakerCredit::default() };
+        assert_eq!(credit.apply(&mut oo).unwrap_err(), error!(DlmmError::EscrowMismatch));
+        MakerCredit { unlock_a: 100, free_a: 100, ..MakerCredit::default() }.apply(&mut oo).unwrap();
+        assert_eq!((oo.base_locked, oo.base_free), (0, 100));
+    }
+
+    #[test]
+    fn crossing_levels_skip_expired_levels() {
+        let mut ob = full_book();
+        let order = |price_1e6, expiry_slot, next| Order {
+            order_id: 1,
+            side: Side::Ask,
+            price_1e6,
+            qty: 1_000,
+            expiry_slot,
+            next,
+            ..Order::empty()
+        };
+        // 1.000: expired only; 1.001: expired head, live order behind it; 1.002: live
+        ob.orders = vec![
+            order(1_000_000, 5, ORDER_NIL),
+            order(1_001_000, 5, 2),
+            order(1_001_000, 50, ORDER_NIL),
+            order(1_002_000, 50, ORDER_NIL),
+        ];
+        let level = |price_1e6, head, tail| PriceLevel { price_1e6, total_qty: 1_000, head, tail };
+        ob.asks = vec![level(1_000_000, 0, 0), level(1_001_000, 1, 2), level(1_002_000, 3, 3)];
+        assert_eq!(crossing_levels(&ob, Side::Ask, 1_001_000, 10), vec![1_001_000]);
+        assert_eq!(crossing_levels(&ob, Side::Ask, 2_000_000, 10), vec![1_001_000, 1_002_000]);
+        assert_eq!(crossing_levels(&ob, Side::Ask, 2_000_000, 5), vec![1_000_000, 1_001_000, 1_002_000]);
+    }
+}
 
 /* =============================================================================
                                    End
@@ -7,6 +7,7 @@ import {
   TOKEN_PROGRAM_ID,
   createMint,
   getOrCreateAssociatedTokenAccount,
+  getAccount,
   mintTo,
 } from "@solana/spl-token";
 import BN from "bn.js";
@@ -217,6 +218,12 @@ describe("yield_sensitive_dlmm end-to-end", () => {
         (program as any).programId
       );
     }
+    async function pdaForOpenOrders(pool: PublicKey, owner: PublicKey) {
+      return await PublicKey.findProgramAddress(
+        [Buffer.from("v3"), Buffer.from("open_orders"), pool.toBuffer(), owner.toBuffer()],
+        (program as any).programId
+      );
+    }
     async function pdaForPosition(pool: PublicKey, owner: PublicKey, receiptNonce: number) {
       const nonceBuf = new BN(receiptNonce).toArrayLike(Buffer, "le", 8);
       return await PublicKey.findProgramAddress(
@@ -390,7 +397,7 @@ describe("yield_sensitive_dlmm end-to-end", () => {
       console.error("Failed to fetch pool account:", err);
       throw err;
     }
-    assert.strictEqual(Number(poolAcct.version), 3, "pool.version should be 3 after init");
+    assert.strictEqual(Number(poolAcct.version), 4, "pool.version should be 4 after init");
 
     // 7) post_yields_and_update (use snake_case account keys)
     console.log("Calling post_yields_and_update...");
@@ -438,10 +445,12 @@ describe("yield_sensitive_dlmm end-to-end", () => {
       if (!method) throw new Error("RPC method `addLiquidity` / `add_liquidity` not found.");
       addSig = await method(
         0, // band_idx (u8)
-        new BN(1000), // amount_a (u64)
+        new BN(100_000), // amount_a (u64); first deposit must clear the locked minimum
         new BN(0), // amount_b (u64)
         new BN(receiptNonce), // receipt_nonce (u64)
-        new BN(0) // min_unlock_after_slots (u64)
+        new BN(0), // min_unlock_after_slots (u64)
+        new BN(1), // min_shares_out (u64)
+        new BN((await connection.getSlot("confirmed")) + 150) // expiry_slot (u64)
       ).accounts({
         user: caller,
         pool: poolPda,
@@ -468,12 +477,51 @@ describe("yield_sensitive_dlmm end-to-end", () => {
     console.log("Position:", {
       owner: posAcct.owner.toBase58(),
       shares: posAcct.shares.toString(),
-      bandIdx: posAcct.band_idx ?? posAcct.bandIdx,
+      bandId: posAcct.band_id ?? posAcct.bandId,
     });
     assert.strictEqual(posAcct.owner.toBase58(), caller.toBase58(), "position owner mismatch");
     assert.ok(Number(posAcct.shares) > 0, "position shares must be > 0");
 
-    // 10) Place an order (Bid) - try both naming conventions if necessary for accounts
+    // 10) Swap B -> A against the band liquidity deposited above
+    console.log("Swapping B for A...");
+    const balABefore = (await getAccount(connection, callerAtaA)).amount;
+    const balBBefore = (await getAccount(connection, callerAtaB)).amount;
+    let swapSig: string | null = null;
+    try {
+      const method = (program as any).methods?.swap;
+      if (!method) throw new Error("RPC method `swap` not found.");
+      const slot = await connection.getSlot("confirmed");
+      swapSig = await method(
+        { Bid: {} }, // pay B, receive A
+        new BN(100), // amount_in (u64)
+        new BN(1), // min_amount_out (u64)
+        new BN(slot + 150) // expiry_slot (u64)
+      ).accounts({
+        user: caller,
+        pool: poolPda,
+        vault_a: vaultAPda,
+        vault_b: vaultBPda,
+        treasury_a: treasuryAPda,
+        treasury_b: treasuryBPda,
+        user_ata_a: callerAtaA,
+        user_ata_b: callerAtaB,
+        token_program: TOKEN_PROGRAM_ID,
+        mint_a: mintA,
+        mint_b: mintB,
+      }).rpc();
+      console.log("swap tx:", swapSig);
+      await connection.confirmTransaction(swapSig, "confirmed");
+    } catch (err: any) {
+      console.error("swap failed:", err);
+      await printTxLogs(err?.tx ?? swapSig ?? null);
+      throw err;
+    }
+    const balAAfter = (await getAccount(connection, callerAtaA)).amount;
+    const balBAfter = (await getAccount(connection, callerAtaB)).amount;
+    assert.ok(balAAfter > balABefore, "swap should pay out token A");
+    assert.ok(balBAfter < balBBefore, "swap should pull token B");
+
+    // 11) Place an order (Bid) - try both naming conventions if necessary for accounts
     console.log("Placing an order (bid)...");
     try {
       const method =
@@ -484,13 +532,24 @@ describe("yield_sensitive_dlmm end-to-end", () => {
         new BN(10), // qty (u64)
         null, // limitPriceOpt1e6
         { kind: 0, gtt_expiry_slot: new BN(0) }, // TifParam
-        false, // postOnly
+        { None: {} }, // postOnly
         false, // reduceOnly
         new BN(42) // clientId
       ).accounts({
         user: caller,
         pool: poolPda,
         orderbook: orderbookPda,
+        vault_a: vaultAPda,
+        vault_b: vaultBPda,
+        treasury_a: treasuryAPda,
+        treasury_b: treasuryBPda,
+        user_ata_a: callerAtaA,
+        user_ata_b: callerAtaB,
+        open_orders: (await pdaForOpenOrders(poolPda, caller))[0],
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+        mint_a: mintA,
+        mint_b: mintB,
       }).rpc();
       console.log("placeOrder tx:", placeSig);
       await connection.confirmTransaction(placeSig, "confirmed");
@@ -499,7 +558,7 @@ describe("yield_sensitive_dlmm end-to-end", () => {
       await printTxLogs(err?.tx ?? null);
     }
 
-    // 11) Final pool check
+    // 12) Final pool check
     const finalPool = await program.account.pool.fetch(poolPda);
     console.log("Final pool snapshot:", {
       version: finalPool.version,
@@ -507,9 +566,449 @@ describe("yield_sensitive_dlmm end-to-end", () => {
       feeCurrentBps: finalPool.fee_current_bps ?? finalPool.feeCurrentBps,
     });
 
-    assert.strictEqual(Number(finalPool.version), 3, "pool.version should remain 3");
+    assert.strictEqual(Number(finalPool.version), 4, "pool.version should remain 4");
     assert.strictEqual(Number(finalPool.n_bands ?? finalPool.nBands), 8, "pool.nBands should be 8");
 
     console.log("End-to-end test finished.");
   }).timeout(120000);
 });
+
+describe("yield_sensitive_dlmm behavior", () => {
+  // shared by the cases below, which run in order against one pool
+  let env: { program: any; connection: any; payer: Keypair };
+  let caller: PublicKey;
+  let mintA: PublicKey, mintB: PublicKey;
+  let poolPda: PublicKey, orderbookPda: PublicKey, openOrdersPda: PublicKey;
+  let vaultA: PublicKey, vaultB: PublicKey, treasuryA: PublicKey, treasuryB: PublicKey;
+  let ataA: PublicKey, ataB: PublicKey;
+
+  const programId = () => (env.program as any).programId as PublicKey;
+  const pda = async (seeds: Buffer[]) => (await PublicKey.findProgramAddress(seeds, programId()))[0];
+  const pick = (o: any, camel: string, snake: string) => o[camel] ?? o[snake];
+  const balance = async (ata: PublicKey) => BigInt((await getAccount(env.connection, ata)).amount.toString());
+  const expiry = async () => new BN((await env.connection.getSlot("confirmed")) + 150);
+
+  /** Sums of band reserves over the pool's live bands. */
+  async function reserveTotals() {
+    const p = await env.program.account.pool.fetch(poolPda);
+    const n = Number(pick(p, "nBands", "n_bands"));
+    let a = BigInt(0), b = BigInt(0);
+    for (const band of p.bands.slice(0, n)) {
+      a += BigInt(pick(band, "reservesA", "reserves_a").toString());
+      b += BigInt(pick(band, "reservesB", "reserves_b").toString());
+    }
+    return { a, b, pool: p, n };
+  }
+
+  /** Anchor events emitted by a confirmed transaction. */
+  async function eventsOf(sig: string) {
+    await env.connection.confirmTransaction(sig, "confirmed");
+    const tx = await env.connection.getTransaction(sig, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
+    const parser = new (anchor as any).EventParser(programId(), new (anchor as any).BorshCoder(env.program.idl));
+    return Array.from(parser.parseLogs(tx?.meta?.logMessages ?? []) as Iterable<any>);
+  }
+  const named = (evs: any[], name: string) => evs.filter((e) => e.name.toLowerCase() === name.toLowerCase());
+
+  async function swap(side: any, amountIn: number) {
+    return await env.program.methods
+      .swap(side, new BN(amountIn), new BN(1), await expiry())
+      .accounts({
+        user: caller,
+        pool: poolPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        user_ata_a: ataA,
+        user_ata_b: ataB,
+        token_program: TOKEN_PROGRAM_ID,
+        mint_a: mintA,
+        mint_b: mintB,
+      })
+      .rpc();
+  }
+
+  /** `quote_swap` for an IOC taker of `qty` base limited at `price1e6`. */
+  async function quote(side: any, qty: number, price1e6: number) {
+    return await env.program.methods
+      .quoteSwap(side, new BN(qty), new BN(price1e6), await expiry())
+      .accounts({ pool: poolPda, orderbook: orderbookPda })
+      .view();
+  }
+
+  async function placeOrder(side: any, qty: number, price1e6: number, tifKind: number) {
+    return await env.program.methods
+      .placeOrder(side, new BN(qty), new BN(price1e6), { kind: tifKind, gtt_expiry_slot: new BN(0) }, { None: {} }, false, new BN(0))
+      .accounts({
+        user: caller,
+        pool: poolPda,
+        orderbook: orderbookPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        user_ata_a: ataA,
+        user_ata_b: ataB,
+        open_orders: openOrdersPda,
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+        mint_a: mintA,
+        mint_b: mintB,
+      })
+      .rpc();
+  }
+
+  const positionPda = async (nonce: number) =>
+    await pda([Buffer.from("v3"), Buffer.from("pos"), poolPda.toBuffer(), caller.toBuffer(), new BN(nonce).toArrayLike(Buffer, "le", 8)]);
+
+  async function addLiquidity(bandIdx: number, amountA: number, amountB: number, nonce: number) {
+    const position = await positionPda(nonce);
+    const sig = await env.program.methods
+      .addLiquidity(bandIdx, new BN(amountA), new BN(amountB), new BN(nonce), new BN(0), new BN(1), await expiry())
+      .accounts({
+        user: caller,
+        pool: poolPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        user_ata_a: ataA,
+        user_ata_b: ataB,
+        position,
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+        mint_a: mintA,
+        mint_b: mintB,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(sig, "confirmed");
+  }
+
+  before(async () => {
+    const { program, connection, payerKeypair } = await resolveEnv();
+    env = { program, connection, payer: payerKeypair };
+    caller = payerKeypair.publicKey;
+
+    mintA = await createMint(connection, payerKeypair, caller, null, 6);
+    mintB = await createMint(connection, payerKeypair, caller, null, 6);
+    poolPda = await pda([Buffer.from("v3"), Buffer.from("pool"), mintA.toBuffer(), mintB.toBuffer()]);
+    vaultA = await pda([Buffer.from("v3"), Buffer.from("vault"), poolPda.toBuffer(), mintA.toBuffer()]);
+    vaultB = await pda([Buffer.from("v3"), Buffer.from("vault"), poolPda.toBuffer(), mintB.toBuffer()]);
+    treasuryA = await pda([Buffer.from("v3"), Buffer.from("treasury"), poolPda.toBuffer(), mintA.toBuffer()]);
+    treasuryB = await pda([Buffer.from("v3"), Buffer.from("treasury"), poolPda.toBuffer(), mintB.toBuffer()]);
+    orderbookPda = await pda([Buffer.from("v3"), Buffer.from("orderbook"), poolPda.toBuffer()]);
+    openOrdersPda = await pda([Buffer.from("v3"), Buffer.from("open_orders"), poolPda.toBuffer(), caller.toBuffer()]);
+
+    ataA = (await getOrCreateAssociatedTokenAccount(connection, payerKeypair, mintA, caller)).address;
+    ataB = (await getOrCreateAssociatedTokenAccount(connection, payerKeypair, mintB, caller)).address;
+    await mintTo(connection, payerKeypair, mintA, ataA, payerKeypair, 10_000_000);
+    await mintTo(connection, payerKeypair, mintB, ataB, payerKeypair, 10_000_000);
+
+    const zeroPub = new PublicKey(new Uint8Array(32));
+    const admins = new Array(8).fill(zeroPub);
+    admins[0] = caller;
+    // bands of 10% around a 1.0 center: with 8 bands, index 3 spans [1.0, 1.1] and index 2 [0.9, 1.0]
+    const params: any = {
+      admins,
+      admin_threshold: 1,
+      risk_admin: caller,
+      ops_admin: caller,
+      fee_admin: caller,
+      updater: caller,
+      oracle_signer: null,
+      n_bands: 8,
+      base_width_bps: 1000,
+      min_width_bps: 500,
+      max_width_bps: 5000,
+      width_slope_per_kbps: 10,
+      bias_per_kbps: 0,
+      decay_per_band_bps: 10,
+      alpha_y_bps: 500,
+      alpha_spot_bps: 500,
+      alpha_twap_bps: 500,
+      alpha_vol_bps: 500,
+      max_twap_dev_bps: 500,
+      fee_base_bps: 10,
+      fee_k_per_bps: 0,
+      fee_max_bps: 200,
+      initial_y_a_bps: 500,
+      initial_y_b_bps: 500,
+      initial_spot_price_1e6: new BN(1_000_000),
+      hyst_center_bps: 10,
+      hyst_width_bps: 10,
+      hyst_required_n: 1,
+      deposit_ratio_min_bps: 100,
+      deposit_ratio_max_bps: 10_000,
+      inactive_floor_a: new BN(0),
+      inactive_floor_b: new BN(0),
+      bounty_rate_microunits: new BN(1_000_000), // one unit per bps of band movement
+      bounty_max: new BN(1_000_000),
+      stale_slots_for_boost: new BN(100),
+      bounty_boost_bps: 1000,
+      min_cu_price: new BN(0),
+      max_center_move_bps: 100,
+      max_width_change_bps: 100,
+      max_weight_shift_bps: 100,
+      min_update_interval_slots: 0,
+      maker_rebate_max_bps: 5,
+      taker_min_bps: 1,
+      stp_mode: 0,
+      route_mode: 2, // BestPrice
+    };
+    const initSig = await program.methods
+      .initializePool(params)
+      .accounts({
+        payer: caller,
+        mint_a: mintA,
+        mint_b: mintB,
+        pool: poolPda,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        token_program: TOKEN_PROGRAM_ID,
+        system_program: SystemProgram.programId,
+      })
+      .rpc();
+    await connection.confirmTransaction(initSig, "confirmed");
+
+    const obSig = await program.methods
+      .initOrderbook(new BN(1_000), 64, new BN(1_000))
+      .accounts({ payer: caller, pool: poolPda, orderbook: orderbookPda, system_program: SystemProgram.programId })
+      .rpc();
+    await connection.confirmTransaction(obSig, "confirmed");
+
+    // token A above the center for bids to buy, token B below it for asks to sell into
+    await addLiquidity(3, 2_000_000, 0, 1);
+    await addLiquidity(2, 0, 2_000_000, 2);
+  });
+
+  it("curve: larger swaps fill at a worse average price", async () => {
+    const small = await quote({ Bid: {} }, 1_000, 2_000_000);
+    const large = await quote({ Bid: {} }, 500_000, 2_000_000);
+    const smallPx = Number(pick(small, "effectivePrice1e6", "effective_price_1e6"));
+    const largePx = Number(pick(large, "effectivePrice1e6", "effective_price_1e6"));
+    assert.ok(smallPx >= 1_000_000, "bids buy token A at or above the band's lower price");
+    assert.ok(largePx > smallPx, `price impact: ${largePx} should exceed ${smallPx}`);
+
+    const smallAsk = await quote({ Ask: {} }, 1_000, 1_000);
+    const largeAsk = await quote({ Ask: {} }, 500_000, 1_000);
+    const smallAskPx = Number(pick(smallAsk, "effectivePrice1e6", "effective_price_1e6"));
+    const largeAskPx = Number(pick(largeAsk, "effectivePrice1e6", "effective_price_1e6"));
+    assert.ok(largeAskPx < smallAskPx, `price impact: ${largeAskPx} should be below ${smallAskPx}`);
+  }).timeout(120000);
+
+  it("BestPrice: splits a crossing order between the bands and the book", async () => {
+    // resting ask inside the A band's range; nothing bids for A at 1.005, so it rests whole
+    const askSig = await placeOrder({ Ask: {} }, 20_000, 1_005_000, 1);
+    await env.connection.confirmTransaction(askSig, "confirmed");
+
+    const q = await quote({ Bid: {} }, 200_000, 1_050_000);
+    const oo0 = await env.program.account.openOrders.fetch(openOrdersPda);
+    const a0 = await balance(ataA);
+    const b0 = await balance(ataB);
+    const evs = await eventsOf(await placeOrder({ Bid: {} }, 200_000, 1_050_000, 0));
+
+    const bookFills = named(evs, "OrderFilledV3").filter((e) => e.data.taker.equals(caller));
+    const bandFills = named(evs, "SwapFilledV").filter((e) => e.data.taker.equals(caller));
+    const bookQty = bookFills.reduce((s, e) => s + BigInt(e.data.qty.toString()), BigInt(0));
+    const bandQty = bandFills.reduce((s, e) => s + BigInt(e.data.qty.toString()), BigInt(0));
+    assert.strictEqual(bookQty.toString(), "20000", "the whole resting ask should be taken");
+    assert.ok(bandQty > BigInt(0), "the bands should fill the rest");
+    assert.strictEqual(named(evs, "OrderRoutedV").length, 0, "the split is reported by the fill events");
+
+    // bands are taken while they are no worse than the book, so they fill before the level
+    const firstBook = evs.findIndex((e) => e.name.toLowerCase() === "orderfilledv3");
+    const firstBand = evs.findIndex((e) => e.name.toLowerCase() === "swapfilledv");
+    assert.ok(firstBand >= 0 && firstBand < firstBook, "bands should fill before the 1.005 level");
+
+    // book proceeds wait in OpenOrders, band proceeds are paid out right away
+    const oo1 = await env.program.account.openOrders.fetch(openOrdersPda);
+    const baseFree = (o: any) => BigInt(pick(o, "baseFree", "base_free").toString());
+    assert.strictEqual((baseFree(oo1) - baseFree(oo0)).toString(), bookQty.toString(), "book fills credit OpenOrders");
+    assert.strictEqual(((await balance(ataA)) - a0).toString(), bandQty.toString(), "band fills pay the ATA");
+
+    // the quote routes the same way
+    assert.strictEqual(pick(q, "bookQty", "book_qty").toString(), bookQty.toString(), "quoted book leg");
+    assert.strictEqual(pick(q, "amountOut", "amount_out").toString(), (bookQty + bandQty).toString(), "quoted output");
+    assert.strictEqual(pick(q, "amountIn", "amount_in").toString(), (b0 - (await balance(ataB))).toString(), "quoted input");
+  }).timeout(120000);
+
+  it("fees: taker fees reach the treasury and the vaults cover band reserves", async () => {
+    // the resting ask was taken above, so this order only reaches the bands
+    const q = await quote({ Bid: {} }, 100_000, 2_000_000);
+    const feePaid = BigInt(pick(q, "feePaid", "fee_paid").toString());
+    const amountIn = BigInt(pick(q, "amountIn", "amount_in").toString());
+    assert.strictEqual(pick(q, "bookQty", "book_qty").toString(), "0", "the book is empty");
+    assert.ok(feePaid > BigInt(0), "a 10 bps fee on 100k should be nonzero");
+
+    const t0 = await balance(treasuryB);
+    const v0 = await balance(vaultB);
+    const r0 = await reserveTotals();
+    await env.connection.confirmTransaction(await placeOrder({ Bid: {} }, 100_000, 2_000_000, 0), "confirmed");
+    const r1 = await reserveTotals();
+
+    assert.strictEqual(((await balance(treasuryB)) - t0).toString(), feePaid.toString(), "treasury receives the fee");
+    assert.strictEqual(((await balance(vaultB)) - v0).toString(), (amountIn - feePaid).toString(), "vault receives the net input");
+    assert.strictEqual((r1.b - r0.b).toString(), (amountIn - feePaid).toString(), "band reserves grow by the net input");
+
+    assert.ok((await balance(vaultA)) >= r1.a, "vault A covers band reserves");
+    assert.ok((await balance(vaultB)) >= r1.b, "vault B covers band reserves");
+    const feesA = BigInt(pick(r1.pool, "protocolFeesA", "protocol_fees_a").toString());
+    const feesB = BigInt(pick(r1.pool, "protocolFeesB", "protocol_fees_b").toString());
+    assert.ok((await balance(treasuryA)) >= feesA, "treasury A covers protocol fees");
+    assert.ok((await balance(treasuryB)) >= feesB, "treasury B covers protocol fees");
+  }).timeout(120000);
+
+  it("bounties: paid from the funded budget only, never from LP or protocol fees", async () => {
+    // book fills above left protocol fees in B; LP fees of the A band sit in the same treasury
+    const fundSig = await env.program.methods
+      .fundBountyBudget(new BN(1_000), new BN(0))
+      .accounts({
+        funder: caller,
+        pool: poolPda,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        funder_ata_a: ataA,
+        funder_ata_b: ataB,
+        token_program: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(fundSig, "confirmed");
+    const budget = (p: any) => BigInt(pick(p, "bountyBudgetA", "bounty_budget_a").toString());
+    const funded = (await reserveTotals()).pool;
+    assert.strictEqual(budget(funded).toString(), "1000", "budget should be credited");
+
+    // move the spot enough to clear hysteresis so the keeper earns a bounty
+    const a0 = await balance(ataA);
+    const updSig = await env.program.methods
+      .postYieldsAndUpdate(500, 500, new BN(1_050_000), new BN(0))
+      .accounts({
+        caller,
+        oracle_signer_opt: null,
+        pool: poolPda,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        caller_ata_a: ataA,
+        caller_ata_b: ataB,
+        mint_a: mintA,
+        mint_b: mintB,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        metrics: null,
+        token_program: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(updSig, "confirmed");
+    const bounty = (await balance(ataA)) - a0;
+    const paid = (await reserveTotals()).pool;
+    assert.ok(bounty > BigInt(0), "the keeper should be paid a bounty");
+    assert.strictEqual((budget(funded) - budget(paid)).toString(), bounty.toString(), "the bounty comes out of the budget");
+
+    // the protocol takes everything it is owed; LP fees must still be collectable afterwards
+    const feesA = BigInt(pick(paid, "protocolFeesA", "protocol_fees_a").toString());
+    const feesB = BigInt(pick(paid, "protocolFeesB", "protocol_fees_b").toString());
+    assert.ok(feesB > BigInt(0), "book fills should have accrued protocol fees");
+    const wSig = await env.program.methods
+      .withdrawProtocolFees(new BN(feesA.toString()), new BN(feesB.toString()))
+      .accounts({
+        fee_admin: caller,
+        pool: poolPda,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        dst_a: ataA,
+        dst_b: ataB,
+        token_program: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(wSig, "confirmed");
+
+    const b0 = await balance(ataB);
+    const cSig = await env.program.methods
+      .collectFees()
+      .accounts({
+        user: caller,
+        pool: poolPda,
+        position: await positionPda(1),
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        user_ata_a: ataA,
+        user_ata_b: ataB,
+        token_program: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(cSig, "confirmed");
+    assert.ok((await balance(ataB)) > b0, "the A band's LP should collect its B fees");
+    assert.ok((await balance(treasuryA)) >= budget(paid), "treasury A still covers the bounty budget");
+  }).timeout(120000);
+
+  it("bands: resize and rebalance keep band reserves whole", async () => {
+    // one proposal: grow to 10 bands and route all swap fees to the protocol
+    const keys = [
+      "n_bands", "base_width_bps", "min_width_bps", "max_width_bps", "width_slope_per_kbps", "bias_per_kbps",
+      "decay_per_band_bps", "alpha_y_bps", "alpha_spot_bps", "alpha_twap_bps", "alpha_vol_bps", "max_twap_dev_bps",
+      "fee_base_bps", "fee_k_per_bps", "fee_max_bps", "hyst_center_bps", "hyst_width_bps", "hyst_required_n",
+      "deposit_ratio_min_bps", "deposit_ratio_max_bps", "inactive_floor_a", "inactive_floor_b",
+      "bounty_rate_microunits", "bounty_max", "stale_slots_for_boost", "bounty_boost_bps", "min_cu_price",
+      "max_center_move_bps", "max_width_change_bps", "max_weight_shift_bps", "min_update_interval_slots",
+      "maker_rebate_max_bps", "taker_min_bps", "stp_mode", "route_mode", "protocol_fee_bps",
+    ];
+    const settable: any = Object.fromEntries(keys.map((k) => [k, null]));
+    settable.n_bands = 10;
+    settable.protocol_fee_bps = 10_000;
+    const quorum = [{ pubkey: caller, isSigner: true, isWritable: false }];
+
+    const start = await reserveTotals();
+    const propSig = await env.program.methods
+      .proposeParams(settable, new BN(0), new BN(1_000))
+      .accounts({ pool: poolPda })
+      .remainingAccounts(quorum)
+      .rpc();
+    await env.connection.confirmTransaction(propSig, "confirmed");
+    const execSig = await env.program.methods.executeParams().accounts({ pool: poolPda }).remainingAccounts(quorum).rpc();
+    await env.connection.confirmTransaction(execSig, "confirmed");
+
+    const resized = await reserveTotals();
+    assert.strictEqual(resized.n, 10, "pool should have 10 bands");
+    assert.strictEqual(resized.a.toString(), start.a.toString(), "resize keeps reserves A");
+    assert.strictEqual(resized.b.toString(), start.b.toString(), "resize keeps reserves B");
+
+    // accrue protocol fees on both sides and deploy them as pool-owned liquidity
+    await env.connection.confirmTransaction(await swap({ Bid: {} }, 400_000), "confirmed");
+    await env.connection.confirmTransaction(await swap({ Ask: {} }, 400_000), "confirmed");
+    const accrued = await reserveTotals();
+    const feesA = BigInt(pick(accrued.pool, "protocolFeesA", "protocol_fees_a").toString());
+    const feesB = BigInt(pick(accrued.pool, "protocolFeesB", "protocol_fees_b").toString());
+    assert.ok(feesA > BigInt(0) && feesB > BigInt(0), "swaps should accrue protocol fees");
+
+    const va0 = await balance(vaultA);
+    const vb0 = await balance(vaultB);
+    const seedSig = await env.program.methods
+      .seedPoolLiquidity(new BN(feesA.toString()), new BN(feesB.toString()))
+      .accounts({
+        fee_admin: caller,
+        pool: poolPda,
+        treasury_a: treasuryA,
+        treasury_b: treasuryB,
+        vault_a: vaultA,
+        vault_b: vaultB,
+        mint_a: mintA,
+        mint_b: mintB,
+        token_program: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await env.connection.confirmTransaction(seedSig, "confirmed");
+    const seeded = await reserveTotals();
+    assert.strictEqual((seeded.a - accrued.a).toString(), ((await balance(vaultA)) - va0).toString(), "seeded A is in band reserves");
+    assert.strictEqual((seeded.b - accrued.b).toString(), ((await balance(vaultB)) - vb0).toString(), "seeded B is in band reserves");
+
+    const rebSig = await env.program.methods
+      .rebalanceBands()
+      .accounts({ caller, pool: poolPda, vault_a: vaultA, vault_b: vaultB, mint_a: mintA, mint_b: mintB })
+      .rpc();
+    await env.connection.confirmTransaction(rebSig, "confirmed");
+    const rebalanced = await reserveTotals();
+    assert.strictEqual(rebalanced.a.toString(), seeded.a.toString(), "rebalance keeps reserves A");
+    assert.strictEqual(rebalanced.b.toString(), seeded.b.toString(), "rebalance keeps reserves B");
+    assert.ok((await balance(vaultA)) >= rebalanced.a, "vault A covers band reserves");
+    assert.ok((await balance(vaultB)) >= rebalanced.b, "vault B covers band reserves");
+  }).timeout(120000);
+});